@@ -1,7 +1,6 @@
 use crate::vector3::Vector3;
 use crate::baseray::BaseRay;
 use crate::baseobject::BaseObject;
-use rand::Rng;
 // test 
 pub struct BaseCamera {
     pub position: Vector3,
@@ -109,30 +108,31 @@ impl BaseCamera {
         self.lower_left_corner = self.position - self.horizontal / 2.0 - self.vertical / 2.0 - self.direction * self.focus_dist;
     }
 
-
-    pub fn random_in_unit_disk() -> Vector3 {
-        let mut rng = rand::thread_rng();
-        loop {
-            let p = Vector3::new(
-                rng.gen_range(-1.0..1.0),
-                rng.gen_range(-1.0..1.0),
-                0.0
-            );
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
-    }
-    pub fn get_ray(&self, s: f32, t: f32) -> BaseRay {
-        let rd = BaseCamera::random_in_unit_disk() * self.lens_radius;
+    // Raggio per il punto (s, t) del piano immagine, con il punto sulla lente preso dal Sampler
+    pub fn get_ray_sampled(&self, s: f32, t: f32, lens: (f32, f32)) -> BaseRay {
+        let rd = BaseCamera::concentric_disk(lens.0, lens.1) * self.lens_radius;
         let offset = self.right * rd.x + self.up * rd.y;
-    
+
         let direction = self.lower_left_corner + self.horizontal * s + self.vertical * t - self.position - offset;
         BaseRay::new(
             self.position + offset,
-            direction.normalize()  // Normalizza la direzione del raggio
+            direction.normalize()
         )
     }
+
+    // Mappa concentrica di Shirley-Chiu dal quadrato [0, 1)² al disco unitario: conserva la stratificazione
+    pub fn concentric_disk(u1: f32, u2: f32) -> Vector3 {
+        let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        let (r, phi) = if a.abs() > b.abs() {
+            (a, std::f32::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (b, std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b))
+        };
+        Vector3::new(r * phi.cos(), r * phi.sin(), 0.0)
+    }
     
     // pub fn get_ray(&self, s: f32, t: f32) -> BaseRay {
     //     let rd = BaseCamera::random_in_unit_disk() * self.lens_radius ;
@@ -1,7 +1,9 @@
 use crate::vector3::Vector3;
 use std::ops::AddAssign;
 use std::ops::DivAssign;
+use std::ops::{Add, Mul, MulAssign};
 
+#[derive(Debug, Clone, Copy)]
 pub struct AGColor {
     pub r: f32,
     pub g: f32,
@@ -11,6 +13,60 @@ impl AGColor {
     pub fn new(r: f32, g: f32, b: f32) -> Self {
         AGColor { r, g, b }
     }
+
+    pub fn black() -> Self {
+        AGColor { r: 0.0, g: 0.0, b: 0.0 }
+    }
+
+    pub fn max_component(&self) -> f32 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    // Luminanza Rec. 709
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
+    }
+}
+
+impl Add for AGColor {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        AGColor::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl Mul for AGColor {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        AGColor::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+impl Mul<f32> for AGColor {
+    type Output = Self;
+    fn mul(self, scalar: f32) -> Self {
+        AGColor::new(self.r * scalar, self.g * scalar, self.b * scalar)
+    }
+}
+
+impl MulAssign for AGColor {
+    fn mul_assign(&mut self, other: Self) {
+        self.r *= other.r;
+        self.g *= other.g;
+        self.b *= other.b;
+    }
+}
+
+impl MulAssign<f32> for AGColor {
+    fn mul_assign(&mut self, scalar: f32) {
+        self.r *= scalar;
+        self.g *= scalar;
+        self.b *= scalar;
+    }
 }
 
 impl DivAssign<f32> for AGColor {
@@ -29,6 +85,7 @@ impl AddAssign for AGColor {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LightType {
     Spot,
     Point,
@@ -36,10 +93,27 @@ pub enum LightType {
     Area,
 }
 
+// Forma delle luci LightType::Area
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AreaShape {
+    Rectangle,  // area_size = (larghezza, altezza)
+    Disk,       // area_size.0 = diametro
+    Sphere,     // area_size.0 = diametro
+}
+
+// Punto campionato su una luce ad area, visto da un punto della scena
+pub struct LightSample {
+    pub direction: Vector3,  // dal punto verso la luce, normalizzata
+    pub distance: f32,
+    pub weight: f32,         // 1 / pdf in angolo solido, già moltiplicato per il coseno di emissione
+}
+
+// Decadimento dell'intensità con la distanza, applicato solo al contributo della propria luce
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FalloffType {
-    None,
-    Linear,
-    Quadratic,
+    None,       // nessun decadimento
+    Linear,     // reference_distance / d, come nelle versioni precedenti (1/d con il valore predefinito)
+    Quadratic,  // (reference_distance / d)^2, l'inverso del quadrato fisico
 }
 
 pub struct BaseLight {
@@ -47,6 +121,11 @@ pub struct BaseLight {
     pub position: Vector3,
     pub direction: Vector3,
     pub color: AGColor,
+    // Irradianza su una superficie perpendicolare alla luce, in unità di PI: lo shading la
+    // moltiplica per PI, che la BRDF lambertiana (albedo / PI) compensa. Una superficie
+    // bianca illuminata frontalmente da una luce di intensità 1 resta quindi bianca, come con
+    // lo shading lambertiano precedente alla GGX: le scene esistenti non cambiano luminosità
+    // nella parte diffusa, solo i riflessi speculari seguono la nuova BRDF.
     pub intensity: f32,
     pub light_type: LightType,
     pub falloff: FalloffType,
@@ -54,10 +133,19 @@ pub struct BaseLight {
     pub inner_radius: f32,
     pub radius_decay: f32,
     pub area_size: (f32, f32),
+    pub angular_radius: f32,  // raggio angolare in gradi delle luci direzionali (0 = ombre nette)
+    pub area_shape: AreaShape,
+    pub reference_distance: f32,  // distanza a cui Linear e Quadratic valgono 1 (predefinita 1 unità di scena)
+    pub max_range: f32,       // oltre questa distanza la luce si spegne con una finestra morbida (0 = illimitata)
+    // False per le luci di riempimento senza ombre. Le liste di inclusione ed esclusione per
+    // oggetto della scheda Progetto di Cinema 4D non ci sono: la scena ha un solo BaseObject.
+    pub casts_shadows: bool,
 }
 
 impl BaseLight {
-    pub fn new(name: String, position: Vector3, direction: Vector3, color: AGColor, intensity: f32, light_type: LightType, falloff: FalloffType, spot_angle: f32, inner_radius: f32, radius_decay: f32, area_size: (f32, f32)) -> Self {
+    // Decadimento, cono dello spot e dimensioni dell'area partono da zero: si impostano sui campi
+    // dopo la costruzione, solo per i tipi di luce che li usano
+    pub fn new(name: String, position: Vector3, direction: Vector3, color: AGColor, intensity: f32, light_type: LightType) -> Self {
         BaseLight {
             name,
             position,
@@ -65,11 +153,315 @@ impl BaseLight {
             color,
             intensity,
             light_type,
-            falloff,
-            spot_angle,
-            inner_radius,
-            radius_decay,
-            area_size,
+            falloff: FalloffType::None,
+            spot_angle: 0.0,
+            inner_radius: 0.0,
+            radius_decay: 0.0,
+            area_size: (0.0, 0.0),
+            angular_radius: 0.0,
+            area_shape: AreaShape::Rectangle,
+            reference_distance: 1.0,
+            max_range: 0.0,
+            casts_shadows: true,
+        }
+    }
+
+    // Fattore di attenuazione a distanza `distance` dalla luce
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        if self.is_infinite() {
+            return 1.0;
+        }
+        let falloff = match self.falloff {
+            FalloffType::None => 1.0,
+            FalloffType::Linear => {
+                let reference = self.reference_distance.max(1e-6);
+                reference / distance.max(reference * 1e-3)
+            },
+            FalloffType::Quadratic => {
+                let reference = self.reference_distance.max(1e-6);
+                let distance = distance.max(reference * 1e-3);
+                (reference * reference) / (distance * distance)
+            },
+        };
+        falloff * self.range_window(distance)
+    }
+
+    // Finestra (1 - (d / max_range)^4)^2: vale ~1 vicino alla luce e va a zero con
+    // derivata nulla a max_range, senza il taglio netto di un clamp
+    fn range_window(&self, distance: f32) -> f32 {
+        if self.max_range <= 0.0 {
+            return 1.0;
+        }
+        let ratio = distance / self.max_range;
+        let window = (1.0 - ratio * ratio * ratio * ratio).clamp(0.0, 1.0);
+        window * window
+    }
+
+    pub fn area(&self) -> f32 {
+        let (width, height) = self.area_size;
+        match self.area_shape {
+            AreaShape::Rectangle => width * height,
+            AreaShape::Disk => std::f32::consts::PI * width * width * 0.25,
+            AreaShape::Sphere => std::f32::consts::PI * width * width,
+        }
+    }
+
+    // Campiona la luce ad area vista da `point` con (u1, u2) in [0, 1).
+    // Rettangolo e disco emettono solo dal lato di `direction` e sono campionati
+    // uniformemente per area; la sfera è campionata nel cono che sottende.
+    // None se il campione non è visibile dal lato che emette.
+    pub fn sample_area(&self, point: &Vector3, u1: f32, u2: f32) -> Option<LightSample> {
+        let normal = self.direction.normalize();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let (width, height) = self.area_size;
+
+        let position = match self.area_shape {
+            AreaShape::Rectangle => {
+                self.position + tangent * ((u1 - 0.5) * width) + bitangent * ((u2 - 0.5) * height)
+            },
+            AreaShape::Disk => {
+                let r = 0.5 * width * u1.sqrt();
+                let phi = 2.0 * std::f32::consts::PI * u2;
+                self.position + tangent * (r * phi.cos()) + bitangent * (r * phi.sin())
+            },
+            AreaShape::Sphere => return self.sample_sphere(point, u1, u2),
+        };
+
+        let to_light = position - *point;
+        let distance_squared = to_light.length_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+        let cos_emission = normal.dot(&-direction);
+        if cos_emission <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            weight: cos_emission * self.area() / distance_squared,
+        })
+    }
+
+    fn sample_sphere(&self, point: &Vector3, u1: f32, u2: f32) -> Option<LightSample> {
+        let radius = 0.5 * self.area_size.0;
+        let to_center = self.position - *point;
+        let center_distance_squared = to_center.length_squared();
+        if center_distance_squared <= radius * radius {
+            return None;  // Punto dentro la sfera
+        }
+        let center_distance = center_distance_squared.sqrt();
+        let axis = to_center / center_distance;
+
+        let sin_max_squared = radius * radius / center_distance_squared;
+        let cos_max = (1.0 - sin_max_squared).max(0.0).sqrt();
+        let cos_theta = 1.0 - u1 * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let direction = axis.from_local(&Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+        // Prima intersezione del raggio con la sfera
+        let b = direction.dot(&to_center);
+        let disc = (b * b - center_distance_squared + radius * radius).max(0.0);
+        let distance = b - disc.sqrt();
+
+        Some(LightSample {
+            direction,
+            distance,
+            weight: 2.0 * std::f32::consts::PI * (1.0 - cos_max),
+        })
+    }
+
+    // Angolo solido sotteso dalla luce ad area vista da `point`, zero dal lato che non emette.
+    // Serve a convertire l'intensità della luce in radianza: come in Cinema 4D una luce ad
+    // area senza falloff illumina quanto un punto luce della stessa intensità, qualunque
+    // sia la sua dimensione apparente.
+    pub fn solid_angle(&self, point: &Vector3) -> f32 {
+        let normal = self.direction.normalize();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let (width, height) = self.area_size;
+        match self.area_shape {
+            AreaShape::Sphere => {
+                let radius = 0.5 * width;
+                let center_distance_squared = (self.position - *point).length_squared();
+                if center_distance_squared <= radius * radius {
+                    return 0.0;
+                }
+                let cos_max = (1.0 - radius * radius / center_distance_squared).max(0.0).sqrt();
+                2.0 * std::f32::consts::PI * (1.0 - cos_max)
+            },
+            _ if normal.dot(&(*point - self.position)) <= 0.0 => 0.0,
+            AreaShape::Rectangle => {
+                let corner = |sx: f32, sy: f32| self.position + tangent * (sx * 0.5 * width) + bitangent * (sy * 0.5 * height) - *point;
+                let (c0, c1, c2, c3) = (corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0));
+                triangle_solid_angle(&c0, &c1, &c2) + triangle_solid_angle(&c0, &c2, &c3)
+            },
+            AreaShape::Disk => {
+                // Poligono regolare inscritto, con l'area riportata a quella del disco
+                const SIDES: usize = 32;
+                let radius = 0.5 * width;
+                let center = self.position - *point;
+                let vertex = |i: usize| {
+                    let phi = 2.0 * std::f32::consts::PI * i as f32 / SIDES as f32;
+                    center + tangent * (radius * phi.cos()) + bitangent * (radius * phi.sin())
+                };
+                let polygon: f32 = (0..SIDES).map(|i| triangle_solid_angle(&center, &vertex(i), &vertex(i + 1))).sum();
+                let area_ratio = std::f32::consts::PI / (0.5 * SIDES as f32 * (2.0 * std::f32::consts::PI / SIDES as f32).sin());
+                polygon * area_ratio
+            },
+        }
+    }
+
+    // Intersezione di un raggio con la superficie della luce ad area, dal lato che emette.
+    // Il peso del campione restituito è lo stesso che darebbe sample_area per quella
+    // direzione, cioè l'inverso della sua pdf in angolo solido.
+    pub fn intersect_area(&self, origin: &Vector3, direction: &Vector3) -> Option<LightSample> {
+        let normal = self.direction.normalize();
+        let (width, height) = self.area_size;
+        if self.area_shape == AreaShape::Sphere {
+            let radius = 0.5 * width;
+            let to_center = self.position - *origin;
+            let center_distance_squared = to_center.length_squared();
+            if center_distance_squared <= radius * radius {
+                return None;
+            }
+            let b = direction.dot(&to_center);
+            let disc = b * b - center_distance_squared + radius * radius;
+            if b <= 0.0 || disc < 0.0 {
+                return None;
+            }
+            let cos_max = (1.0 - radius * radius / center_distance_squared).max(0.0).sqrt();
+            return Some(LightSample {
+                direction: *direction,
+                distance: b - disc.sqrt(),
+                weight: 2.0 * std::f32::consts::PI * (1.0 - cos_max),
+            });
+        }
+
+        let cos_emission = normal.dot(&-*direction);
+        if cos_emission <= 0.0 {
+            return None;
+        }
+        let distance = normal.dot(&(*origin - self.position)) / cos_emission;
+        if distance <= 0.0 {
+            return None;
+        }
+        let local = *origin + *direction * distance - self.position;
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let (x, y) = (local.dot(&tangent), local.dot(&bitangent));
+        let inside = match self.area_shape {
+            AreaShape::Rectangle => x.abs() <= 0.5 * width && y.abs() <= 0.5 * height,
+            _ => x * x + y * y <= 0.25 * width * width,
+        };
+        if !inside {
+            return None;
+        }
+        Some(LightSample {
+            direction: *direction,
+            distance,
+            weight: cos_emission * self.area() / (distance * distance),
+        })
+    }
+
+    // Attenuazione del cono di uno spot per un punto visto dalla luce lungo `to_point`
+    // (normalizzato). Come in Cinema 4D, spot_angle è l'angolo esterno e inner_radius
+    // l'angolo interno, entrambi come apertura totale in gradi: dentro il cono interno
+    // l'intensità è piena, fra i due coni sfuma con una smoothstep, fuori è zero.
+    pub fn spot_attenuation(&self, to_point: &Vector3) -> f32 {
+        let outer = (self.spot_angle * 0.5).to_radians();
+        let inner = (self.inner_radius * 0.5).to_radians().min(outer);
+        let cos_outer = outer.cos();
+        let cos_inner = inner.cos();
+        let cos_angle = self.direction.normalize().dot(to_point);
+
+        if cos_angle <= cos_outer {
+            return 0.0;
+        }
+        if cos_angle >= cos_inner || cos_inner - cos_outer <= 1e-6 {
+            return 1.0;
         }
+        let t = (cos_angle - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    // Luci all'infinito: nessuna posizione, nessun falloff, ombre senza limite di distanza
+    pub fn is_infinite(&self) -> bool {
+        matches!(self.light_type, LightType::Directional)
+    }
+
+    // Direzione verso una luce direzionale, campionata uniformemente nel cono del suo
+    // raggio angolare; con angular_radius = 0 è sempre -direction (ombre nette)
+    pub fn sample_directional(&self, u1: f32, u2: f32) -> Vector3 {
+        let to_light = -self.direction.normalize();
+        if self.angular_radius <= 0.0 {
+            return to_light;
+        }
+        let cos_max = self.angular_radius.to_radians().cos();
+        let cos_theta = 1.0 - u1 * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        to_light.from_local(&Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+
+    // Luce direzionale: conta solo la direzione, la posizione non è usata
+    pub fn new_directional(name: String, direction: Vector3, color: AGColor, intensity: f32, angular_radius: f32) -> Self {
+        let mut light = BaseLight::new(name, Vector3::zero(), direction.normalize(), color, intensity, LightType::Directional);
+        light.angular_radius = angular_radius.max(0.0);
+        light
+    }
+}
+
+// Angolo solido del triangolo sferico con vertici nelle direzioni a, b, c
+// (Van Oosterom e Strackee, 1983)
+fn triangle_solid_angle(a: &Vector3, b: &Vector3, c: &Vector3) -> f32 {
+    let (la, lb, lc) = (a.length(), b.length(), c.length());
+    let numerator = a.dot(&b.cross(c)).abs();
+    let denominator = la * lb * lc + a.dot(b) * lc + a.dot(c) * lb + b.dot(c) * la;
+    2.0 * numerator.atan2(denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Spot rivolto verso -y con cono di 60 gradi e parte piena di 20 gradi
+    fn spot() -> BaseLight {
+        let mut light = BaseLight::new("Spot".to_string(), Vector3::zero(), Vector3::new(0.0, -2.0, 0.0), AGColor::new(1.0, 1.0, 1.0), 1.0, LightType::Spot);
+        light.spot_angle = 60.0;
+        light.inner_radius = 20.0;
+        light
+    }
+
+    // Direzione dalla luce verso il punto, a `degrees` gradi dall'asse dello spot
+    fn off_axis(degrees: f32) -> Vector3 {
+        let angle = degrees.to_radians();
+        Vector3::new(angle.sin(), -angle.cos(), 0.0)
+    }
+
+    #[test]
+    fn spot_attenuation_blends_between_cones() {
+        let light = spot();
+        assert_eq!(light.spot_attenuation(&off_axis(0.0)), 1.0);
+        assert_eq!(light.spot_attenuation(&off_axis(9.0)), 1.0);
+        assert_eq!(light.spot_attenuation(&off_axis(31.0)), 0.0);
+        assert_eq!(light.spot_attenuation(&off_axis(90.0)), 0.0);
+
+        // Fra i due coni il fattore scende senza salti
+        let mut previous = 1.0;
+        for degrees in 10..30 {
+            let attenuation = light.spot_attenuation(&off_axis(degrees as f32 + 0.5));
+            assert!(attenuation > 0.0 && attenuation < 1.0 && attenuation <= previous, "{} gradi: {}", degrees, attenuation);
+            previous = attenuation;
+        }
+    }
+
+    #[test]
+    fn spot_without_inner_cone_has_hard_edge() {
+        let mut light = spot();
+        light.inner_radius = 0.0;
+        assert!(light.spot_attenuation(&off_axis(29.0)) > 0.0);
+        assert_eq!(light.spot_attenuation(&off_axis(31.0)), 0.0);
     }
 }
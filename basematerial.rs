@@ -0,0 +1,373 @@
+use crate::vector3::Vector3;
+use crate::baselight::AGColor;
+use crate::texture::{Texture, TextureContext};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaterialType {
+    Standard,  // metallic/roughness GGX
+    Mirror,    // specchio perfetto o lucido (roughness > 0)
+    Glass,     // dielettrico trasparente con rifrazione (resina, vetro)
+}
+
+// Materiale PBR metallic/roughness (stesso modello di Substance e del Principled di Blender):
+// diffuso di Lambert + lobo speculare GGX con geometria di Smith e Fresnel di Schlick.
+#[derive(Debug, Clone)]
+pub struct BaseMaterial {
+    pub name: String,
+    pub base_color: AGColor,
+    pub metallic: f32,     // 0 = dielettrico, 1 = metallo
+    pub roughness: f32,    // roughness percettiva, alpha = roughness^2
+    pub specular: f32,     // riflettanza dei dielettrici, 0.5 => F0 = 0.04
+    pub material_type: MaterialType,
+    pub ior: f32,          // indice di rifrazione per MaterialType::Glass
+    pub absorption: AGColor, // coefficiente di Beer-Lambert per unità di scena (resina colorata)
+    pub emission: AGColor,   // radianza emessa dal lato della normale; non nera = l'oggetto è una luce
+
+    // Texture opzionali: se presenti sostituiscono il valore costante corrispondente
+    pub base_color_texture: Option<Arc<Texture>>,
+    pub roughness_texture: Option<Arc<Texture>>,   // canale rosso
+    pub normal_texture: Option<Arc<Texture>>,      // normal map in spazio tangente (OpenGL, +Y)
+}
+
+impl BaseMaterial {
+    // Sotto questo valore di alpha il lobo GGX diventa numericamente instabile
+    const MIN_ALPHA: f32 = 0.002;
+
+    pub fn new(name: String, base_color: AGColor, metallic: f32, roughness: f32) -> Self {
+        BaseMaterial {
+            name,
+            base_color,
+            metallic: metallic.clamp(0.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+            specular: 0.5,
+            material_type: MaterialType::Standard,
+            ior: 1.5,
+            absorption: AGColor::black(),
+            emission: AGColor::black(),
+            base_color_texture: None,
+            roughness_texture: None,
+            normal_texture: None,
+        }
+    }
+
+    pub fn new_mirror(name: String, color: AGColor, roughness: f32) -> Self {
+        let mut material = BaseMaterial::new(name, color, 1.0, roughness);
+        material.material_type = MaterialType::Mirror;
+        material
+    }
+
+    // Vetro o resina trasparente. Il colore di trasmissione è quello che si ottiene dopo aver
+    // attraversato `at_distance` unità di materiale; (1, 1, 1) significa nessun assorbimento.
+    pub fn new_glass(name: String, ior: f32, transmission_color: AGColor, at_distance: f32, roughness: f32) -> Self {
+        let mut material = BaseMaterial::new(name, AGColor::new(1.0, 1.0, 1.0), 0.0, roughness);
+        material.material_type = MaterialType::Glass;
+        material.ior = ior.max(1.0);
+        let distance = at_distance.max(1e-6);
+        material.absorption = AGColor::new(
+            -transmission_color.r.clamp(1e-6, 1.0).ln() / distance,
+            -transmission_color.g.clamp(1e-6, 1.0).ln() / distance,
+            -transmission_color.b.clamp(1e-6, 1.0).ln() / distance,
+        );
+        material
+    }
+
+    pub fn has_textures(&self) -> bool {
+        self.base_color_texture.is_some() || self.roughness_texture.is_some()
+    }
+
+    pub fn uses_curvature(&self) -> bool {
+        [&self.base_color_texture, &self.roughness_texture, &self.normal_texture]
+            .iter()
+            .any(|texture| texture.as_ref().map_or(false, |texture| texture.uses_curvature()))
+    }
+
+    // Materiale con gli input presi dalle texture nel punto colpito.
+    // Senza texture restituisce il materiale stesso, senza copie.
+    pub fn at_hit(&self, ctx: &TextureContext) -> Cow<'_, BaseMaterial> {
+        if !self.has_textures() {
+            return Cow::Borrowed(self);
+        }
+        let mut material = self.clone();
+        if let Some(texture) = &self.base_color_texture {
+            material.base_color = texture.evaluate(ctx);
+        }
+        if let Some(texture) = &self.roughness_texture {
+            material.roughness = texture.evaluate(ctx).r.clamp(0.0, 1.0);
+        }
+        Cow::Owned(material)
+    }
+
+    // Normale di shading perturbata dalla normal map; `normal` è già rivolta verso l'osservatore
+    pub fn shading_normal(&self, ctx: &TextureContext, normal: &Vector3) -> Vector3 {
+        let texture = match &self.normal_texture {
+            Some(texture) => texture,
+            None => return *normal,
+        };
+        let t = texture.evaluate(ctx);
+        let local = Vector3::new(t.r * 2.0 - 1.0, t.g * 2.0 - 1.0, t.b * 2.0 - 1.0);
+        // La base tangente segue l'orientamento della normale di shading
+        let sign = if normal.dot(&ctx.normal) < 0.0 { -1.0 } else { 1.0 };
+        let perturbed = (ctx.tangent * local.x + ctx.bitangent * local.y) * sign + *normal * local.z;
+        if perturbed.length_squared() <= 0.0 {
+            return *normal;
+        }
+        perturbed.normalize()
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        !self.emission.is_black()
+    }
+
+    // Radianza emessa verso l'osservatore: i triangoli emettono solo dal lato della normale geometrica
+    pub fn emitted(&self, front_face: bool) -> AGColor {
+        if front_face { self.emission } else { AGColor::black() }
+    }
+
+    // Materiali con lobi delta (o quasi): vengono trattati con riflessione/rifrazione esplicita
+    pub fn is_specular(&self) -> bool {
+        self.material_type != MaterialType::Standard
+    }
+
+    // Trasmittanza di Beer-Lambert dopo aver percorso `distance` all'interno del materiale
+    pub fn transmittance(&self, distance: f32) -> AGColor {
+        AGColor::new(
+            (-self.absorption.r * distance).exp(),
+            (-self.absorption.g * distance).exp(),
+            (-self.absorption.b * distance).exp(),
+        )
+    }
+
+    // Normale della microfaccetta per la riflessione/rifrazione lucida: con roughness
+    // nulla coincide con la normale geometrica.
+    fn microfacet_normal(&self, n: &Vector3, u1: f32, u2: f32) -> Vector3 {
+        if self.roughness <= 0.0 {
+            return *n;
+        }
+        let a2 = self.alpha() * self.alpha();
+        let cos2 = (1.0 - u1) / (1.0 + (a2 - 1.0) * u1);
+        let cos_theta = cos2.sqrt();
+        let sin_theta = (1.0 - cos2).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        n.from_local(&Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+
+    // Lobi di riflessione e trasmissione per Mirror e Glass, ciascuno con direzione e peso.
+    // n è rivolta verso l'osservatore, v è la direzione verso l'osservatore, front_face indica
+    // se il raggio sta entrando nel materiale. (u1, u2) servono solo per i lobi lucidi.
+    pub fn specular_lobes(&self, n: &Vector3, v: &Vector3, front_face: bool, u1: f32, u2: f32) -> (Option<(Vector3, AGColor)>, Option<(Vector3, AGColor)>) {
+        let h = self.microfacet_normal(n, u1, u2);
+        let incident = -*v;
+        let reflected = incident.reflect(&h);
+        let reflection = if reflected.dot(n) > 0.0 { Some(reflected) } else { None };
+
+        match self.material_type {
+            MaterialType::Glass => {
+                let eta = if front_face { 1.0 / self.ior } else { self.ior };
+                let cos_i = v.dot(&h).max(0.0);
+                match incident.refract(&h, eta) {
+                    Some(refracted) if refracted.dot(n) < 0.0 => {
+                        let f = fresnel_dielectric(cos_i, eta);
+                        let white = AGColor::new(1.0, 1.0, 1.0);
+                        (
+                            reflection.map(|dir| (dir, white * f)),
+                            Some((refracted, white * (1.0 - f))),
+                        )
+                    },
+                    // Riflessione totale interna
+                    _ => (reflection.map(|dir| (dir, AGColor::new(1.0, 1.0, 1.0))), None),
+                }
+            },
+            _ => {
+                let f = schlick_fresnel(&self.base_color, v.dot(&h).max(0.0));
+                (reflection.map(|dir| (dir, f)), None)
+            }
+        }
+    }
+
+    // Sceglie uno solo dei lobi speculari, con probabilità proporzionale al suo peso
+    pub fn sample_specular(&self, n: &Vector3, v: &Vector3, front_face: bool, u: (f32, f32, f32)) -> Option<(Vector3, AGColor)> {
+        match self.specular_lobes(n, v, front_face, u.1, u.2) {
+            (Some(r), Some(t)) => {
+                let p_reflect = r.1.luminance() / (r.1.luminance() + t.1.luminance()).max(1e-6);
+                if u.0 < p_reflect {
+                    Some((r.0, r.1 * (1.0 / p_reflect)))
+                } else {
+                    Some((t.0, t.1 * (1.0 / (1.0 - p_reflect))))
+                }
+            },
+            (Some(r), None) => Some(r),
+            (None, Some(t)) => Some(t),
+            (None, None) => None,
+        }
+    }
+
+    // Materiale luminoso (canale Luminanza di Cinema 4D): l'oggetto diventa una luce ad area a forma di mesh
+    pub fn new_emissive(name: String, color: AGColor, strength: f32) -> Self {
+        let mut material = BaseMaterial::new(name, AGColor::black(), 0.0, 1.0);
+        material.emission = color * strength.max(0.0);
+        material
+    }
+    pub fn default_material() -> Self {
+        BaseMaterial::new("Default".to_string(), AGColor::new(0.75, 0.75, 0.75), 0.0, 0.5)
+    }
+
+    #[inline]
+    pub fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(Self::MIN_ALPHA)
+    }
+
+    // Riflettanza a incidenza normale: 0.08 * specular per i dielettrici, base_color per i metalli
+    #[inline]
+    pub fn f0(&self) -> AGColor {
+        let dielectric = 0.08 * self.specular;
+        AGColor::new(
+            lerp(dielectric, self.base_color.r, self.metallic),
+            lerp(dielectric, self.base_color.g, self.metallic),
+            lerp(dielectric, self.base_color.b, self.metallic),
+        )
+    }
+
+    // Valuta la BRDF per la normale n, la direzione di vista v e la direzione della luce l
+    // (tutte normalizzate e uscenti dalla superficie). Il coseno n·l NON è incluso.
+    pub fn eval(&self, n: &Vector3, v: &Vector3, l: &Vector3) -> AGColor {
+        let n_dot_l = n.dot(l);
+        let n_dot_v = n.dot(v);
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+            return AGColor::new(0.0, 0.0, 0.0);
+        }
+
+        let h = (*v + *l).normalize();
+        let n_dot_h = n.dot(&h).max(0.0);
+        let v_dot_h = v.dot(&h).max(0.0);
+
+        let alpha = self.alpha();
+        let d = ggx_d(n_dot_h, alpha);
+        let g = smith_g(n_dot_v, n_dot_l, alpha);
+        let f = schlick_fresnel(&self.f0(), v_dot_h);
+
+        let spec = d * g / (4.0 * n_dot_v * n_dot_l);
+
+        // L'energia riflessa dal lobo speculare non è disponibile per il diffuso
+        let kd = (1.0 - self.metallic) / PI;
+        AGColor::new(
+            (1.0 - f.r) * kd * self.base_color.r + f.r * spec,
+            (1.0 - f.g) * kd * self.base_color.g + f.g * spec,
+            (1.0 - f.b) * kd * self.base_color.b + f.b * spec,
+        )
+    }
+
+    // Probabilità di campionare il lobo speculare invece del diffuso, stimata dal Fresnel
+    pub fn specular_probability(&self, n_dot_v: f32) -> f32 {
+        let spec = schlick_fresnel(&self.f0(), n_dot_v).luminance();
+        let diff = (1.0 - self.metallic) * self.base_color.luminance() * (1.0 - spec);
+        if spec + diff <= 0.0 {
+            return 0.5;
+        }
+        (spec / (spec + diff)).clamp(0.0, 1.0)
+    }
+
+    // Pdf (in angolo solido) con cui sample() genera la direzione l
+    pub fn pdf(&self, n: &Vector3, v: &Vector3, l: &Vector3) -> f32 {
+        let n_dot_l = n.dot(l);
+        let n_dot_v = n.dot(v);
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+            return 0.0;
+        }
+        let h = (*v + *l).normalize();
+        let n_dot_h = n.dot(&h).max(0.0);
+        let v_dot_h = v.dot(&h).max(1e-6);
+
+        let p_spec = self.specular_probability(n_dot_v);
+        let pdf_spec = ggx_d(n_dot_h, self.alpha()) * n_dot_h / (4.0 * v_dot_h);
+        let pdf_diff = n_dot_l / PI;
+        p_spec * pdf_spec + (1.0 - p_spec) * pdf_diff
+    }
+
+    // Campiona una direzione l secondo la BRDF. Restituisce la direzione, il peso
+    // f * cos / pdf da moltiplicare al throughput e la pdf.
+    pub fn sample(&self, n: &Vector3, v: &Vector3, u: (f32, f32, f32)) -> Option<(Vector3, AGColor, f32)> {
+        let n_dot_v = n.dot(v);
+        if n_dot_v <= 0.0 {
+            return None;
+        }
+
+        let p_spec = self.specular_probability(n_dot_v);
+        let l = if u.0 < p_spec {
+            // Campionamento di D(h) * cos(theta_h) per la normale della microfaccetta
+            let a2 = self.alpha() * self.alpha();
+            let cos2 = (1.0 - u.1) / (1.0 + (a2 - 1.0) * u.1);
+            let cos_theta = cos2.sqrt();
+            let sin_theta = (1.0 - cos2).max(0.0).sqrt();
+            let phi = 2.0 * PI * u.2;
+            let h = n.from_local(&Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+            h * (2.0 * v.dot(&h)) - *v
+        } else {
+            n.from_local(&Vector3::cosine_sample_hemisphere(u.1, u.2))
+        };
+
+        let n_dot_l = n.dot(&l);
+        if n_dot_l <= 0.0 {
+            return None;
+        }
+        let pdf = self.pdf(n, v, &l);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let weight = self.eval(n, v, &l) * (n_dot_l / pdf);
+        Some((l, weight, pdf))
+    }
+}
+
+#[inline]
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Distribuzione delle normali GGX / Trowbridge-Reitz
+#[inline]
+pub fn ggx_d(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+// Termine di mascheramento di Smith per una singola direzione
+#[inline]
+pub fn smith_g1(n_dot_x: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let cos2 = n_dot_x * n_dot_x;
+    2.0 * n_dot_x / (n_dot_x + (a2 + (1.0 - a2) * cos2).sqrt())
+}
+
+// Smith separabile: G = G1(v) * G1(l)
+#[inline]
+pub fn smith_g(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    smith_g1(n_dot_v, alpha) * smith_g1(n_dot_l, alpha)
+}
+
+#[inline]
+pub fn schlick_fresnel(f0: &AGColor, cos_theta: f32) -> AGColor {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    let m5 = m * m * m * m * m;
+    AGColor::new(
+        f0.r + (1.0 - f0.r) * m5,
+        f0.g + (1.0 - f0.g) * m5,
+        f0.b + (1.0 - f0.b) * m5,
+    )
+}
+
+// Fresnel esatto per un dielettrico non polarizzato; eta = n_incidente / n_trasmesso
+#[inline]
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
@@ -1,10 +1,12 @@
 use std::fs::File;
 use std::io::BufReader;
-// use std::collections::HashMap;
+use std::collections::HashMap;
 use crate::boundingbox::Boundingbox;
 use crate::vector3::Vector3;
 use crate::matrix::Matrix;
 use crate::bvhnode::BvhNode;
+use crate::baseray::BaseRay;
+use crate::texture::TextureContext;
 use byteorder::{ReadBytesExt, LittleEndian};
 use std::io::Read;
 
@@ -22,11 +24,13 @@ pub struct BaseObject {
     pub padr: Vec<Vector3>,          // Lista dei vertici
     pub uvw: Option<Vec<Vector3>>,           // Lista delle coordinate uv
     pub phong_normal: Option<Vec<Vector3>>,  // Lista delle normali di Phong
+    pub curvature: Option<Vec<f32>>,         // Curvatura per vertice (vedi compute_curvature)
 
     // liste di dati relative ai triangoli -------------------------------------
     pub vadr: Vec<Triangle>,         // Lista dei triangoli, che sono proprietà di BaseObject
     pub norm: Vec<Vector3>,          // Lista delle normali
     pub tri_bbox: Vec<Boundingbox>,  // Lista delle bounding box dei triangoli
+    pub tri_material: Option<Vec<u64>>,  // Materiale per triangolo, se diverso da `material`
 
     pub mg: Matrix,                  // Matrice di trasformazione
     pub boundingbox: Option<Boundingbox>,    
@@ -44,8 +48,10 @@ impl BaseObject {
             padr: Vec::new(),
             uvw: None,
             phong_normal: None,
+            curvature: None,
             vadr: Vec::new(),
             tri_bbox: Vec::new(),
+            tri_material: None,
             norm: Vec::new(),
             mg: Matrix::identity(),
             boundingbox: None,
@@ -112,6 +118,161 @@ impl BaseObject {
         )
     }
 
+    // Informazioni sul punto colpito da un raggio a distanza `distance` sul triangolo indicato
+    // Materiale del triangolo: quello per triangolo se presente, altrimenti quello dell'oggetto
+    pub fn material_of(&self, triangle_index: usize) -> u64 {
+        self.tri_material.as_ref().and_then(|materials| materials.get(triangle_index).copied()).unwrap_or(self.material)
+    }
+
+    pub fn surface_hit(&self, ray: &BaseRay, distance: f32, triangle_index: usize) -> SurfaceHit {
+        let point = ray.origin + ray.direction * distance;
+        let geometric_normal = self.norm[triangle_index];
+        // Shading a due facce: la normale viene girata verso l'osservatore
+        let front_face = geometric_normal.dot(&ray.direction) <= 0.0;
+        let normal = if front_face { geometric_normal } else { -geometric_normal };
+        SurfaceHit {
+            point,
+            normal,
+            geometric_normal,
+            front_face,
+            distance,
+            triangle_index,
+        }
+    }
+
+    // Coordinate baricentriche (w, u, v) del punto p rispetto ai vertici (a, b, c)
+    pub fn barycentric(&self, triangle_index: usize, p: &Vector3) -> (f32, f32, f32) {
+        let triangle = &self.vadr[triangle_index];
+        let a = self.padr[triangle.a];
+        let e1 = self.padr[triangle.b] - a;
+        let e2 = self.padr[triangle.c] - a;
+        let ep = *p - a;
+        let d11 = e1.dot(&e1);
+        let d12 = e1.dot(&e2);
+        let d22 = e2.dot(&e2);
+        let dp1 = ep.dot(&e1);
+        let dp2 = ep.dot(&e2);
+        let denom = d11 * d22 - d12 * d12;
+        if denom.abs() < 1e-20 {
+            return (1.0, 0.0, 0.0);
+        }
+        let u = (d22 * dp1 - d12 * dp2) / denom;
+        let v = (d11 * dp2 - d12 * dp1) / denom;
+        (1.0 - u - v, u, v)
+    }
+
+    // Coordinate uv interpolate nel punto colpito, se l'oggetto ha una lista uvw
+    pub fn interpolate_uv(&self, triangle_index: usize, p: &Vector3) -> Option<(f32, f32)> {
+        let uvw = self.uvw.as_ref()?;
+        let triangle = &self.vadr[triangle_index];
+        let (w, u, v) = self.barycentric(triangle_index, p);
+        let uv = uvw[triangle.a] * w + uvw[triangle.b] * u + uvw[triangle.c] * v;
+        Some((uv.x, uv.y))
+    }
+
+    // Tangente e bitangente (dp/du, dp/dv) del triangolo e scala uv/spazio scena.
+    // Senza uv si usa una base qualsiasi attorno alla normale e scala 1.
+    pub fn uv_frame(&self, triangle_index: usize) -> (Vector3, Vector3, f32) {
+        let normal = self.norm[triangle_index].normalize();
+        let fallback = {
+            let (t, b) = normal.orthonormal_basis();
+            (t, b, 1.0)
+        };
+        let uvw = match self.uvw.as_ref() {
+            Some(uvw) => uvw,
+            None => return fallback,
+        };
+
+        let triangle = &self.vadr[triangle_index];
+        let e1 = self.padr[triangle.b] - self.padr[triangle.a];
+        let e2 = self.padr[triangle.c] - self.padr[triangle.a];
+        let duv1 = uvw[triangle.b] - uvw[triangle.a];
+        let duv2 = uvw[triangle.c] - uvw[triangle.a];
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if det.abs() < 1e-12 {
+            return fallback;
+        }
+        let r = 1.0 / det;
+        let tangent = ((e1 * duv2.y - e2 * duv1.y) * r).normalize();
+        let bitangent = ((e2 * duv1.x - e1 * duv2.x) * r).normalize();
+
+        let world_area = e1.cross(&e2).length();
+        let uv_scale = if world_area > 0.0 { (det.abs() / world_area).sqrt() } else { 1.0 };
+        (tangent, bitangent, uv_scale)
+    }
+
+    // Contesto per la valutazione delle texture nel punto colpito
+    pub fn texture_context(&self, hit: &SurfaceHit, footprint: f32) -> TextureContext {
+        let (tangent, bitangent, uv_scale) = self.uv_frame(hit.triangle_index);
+        TextureContext {
+            uv: self.interpolate_uv(hit.triangle_index, &hit.point),
+            position: hit.point,
+            normal: hit.geometric_normal,
+            tangent,
+            bitangent,
+            footprint,
+            uv_scale,
+            curvature: self.interpolate_curvature(hit.triangle_index, &hit.point),
+        }
+    }
+
+    // Stima della curvatura per vertice, usata dalla texture di usura degli spigoli.
+    // I vertici degli STL non sono condivisi: vengono uniti con snap_to_grid e per ogni
+    // posizione si media la normale delle facce. Lo scarto fra normale media e normale
+    // della faccia, proiettato verso l'esterno del triangolo, è positivo sugli spigoli
+    // convessi e negativo nelle cavità.
+    pub fn compute_curvature(&mut self) {
+        let grid_size = self.boundingbox.as_ref().map(|bbox| bbox.diagonal() * 1e-5).unwrap_or(1e-4).max(1e-6);
+        let key = |v: &Vector3| {
+            let snapped = Self::snap_to_grid(*v, grid_size);
+            (
+                (snapped.x / grid_size).round() as i64,
+                (snapped.y / grid_size).round() as i64,
+                (snapped.z / grid_size).round() as i64,
+            )
+        };
+
+        // Normali delle facce pesate per l'area (il prodotto vettoriale non normalizzato)
+        let mut vertex_normals: HashMap<(i64, i64, i64), Vector3> = HashMap::new();
+        for triangle in &self.vadr {
+            let (a, b, c) = (self.padr[triangle.a], self.padr[triangle.b], self.padr[triangle.c]);
+            let face = (b - a).cross(&(c - a));
+            for v in [a, b, c] {
+                *vertex_normals.entry(key(&v)).or_insert(Vector3::zero()) += face;
+            }
+        }
+
+        let mut curvature = vec![0.0; self.padr.len()];
+        for triangle in &self.vadr {
+            let (a, b, c) = (self.padr[triangle.a], self.padr[triangle.b], self.padr[triangle.c]);
+            let face = (b - a).cross(&(c - a));
+            if face.length_squared() <= 0.0 {
+                continue;
+            }
+            let face = face.normalize();
+            let centroid = (a + b + c) / 3.0;
+            for (index, v) in [(triangle.a, a), (triangle.b, b), (triangle.c, c)] {
+                let average = vertex_normals[&key(&v)];
+                let outward = v - centroid;
+                if average.length_squared() <= 0.0 || outward.length_squared() <= 0.0 {
+                    continue;
+                }
+                curvature[index] = (average.normalize() - face).dot(&outward.normalize());
+            }
+        }
+        self.curvature = Some(curvature);
+    }
+
+    pub fn interpolate_curvature(&self, triangle_index: usize, p: &Vector3) -> f32 {
+        let curvature = match self.curvature.as_ref() {
+            Some(curvature) => curvature,
+            None => return 0.0,
+        };
+        let triangle = &self.vadr[triangle_index];
+        let (w, u, v) = self.barycentric(triangle_index, p);
+        curvature[triangle.a] * w + curvature[triangle.b] * u + curvature[triangle.c] * v
+    }
+
     fn calculate_bounding_box(vertices: &[Vector3]) -> Boundingbox {
         let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
         let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
@@ -140,6 +301,17 @@ impl BaseObject {
     }
 }
 
+// Punto di intersezione con i dati di shading
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceHit {
+    pub point: Vector3,
+    pub normal: Vector3,            // normale di shading, rivolta verso l'osservatore
+    pub geometric_normal: Vector3,  // normale del triangolo così come è nel file
+    pub front_face: bool,           // true se il raggio arriva dal lato della normale geometrica
+    pub distance: f32,
+    pub triangle_index: usize,
+}
+
 #[derive(Debug)]
 pub struct Triangle{
     pub a: usize,
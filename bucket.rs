@@ -1,5 +1,8 @@
 use std::vec::Vec;
+use std::collections::HashMap;
+use std::sync::Mutex;
 use iced::Color;
+use crate::baselight::AGColor;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BucketState {
@@ -53,4 +56,73 @@ pub struct Rect {
     pub y: u32,
     pub width: u32,
     pub height: u32,
-}
\ No newline at end of file
+}
+
+impl Rect {
+    // Rettangolo allargato di `margin` pixel per lato, limitato all'immagine width x height
+    pub fn with_margin(&self, margin: u32, width: u32, height: u32) -> Rect {
+        let (x0, y0) = (self.x.saturating_sub(margin), self.y.saturating_sub(margin));
+        let (x1, y1) = ((self.x + self.width + margin).min(width), (self.y + self.height + margin).min(height));
+        Rect { x: x0, y: y0, width: x1 - x0, height: y1 - y0 }
+    }
+}
+
+// Campioni di un pixel (posizione sulla pellicola e colore) e numero di campioni usati
+pub type PixelSamples = (Vec<(f32, f32, AGColor)>, u32);
+
+// Campioni dei pixel che servono a più bucket, cioè quelli entro il margine del filtro dal
+// bordo di un bucket. Il primo bucket che li chiede li calcola, gli altri li riprendono da qui;
+// un pixel viene tolto quando l'ultimo bucket che lo usa l'ha letto.
+pub struct BorderSamples {
+    width: u32,
+    uses: Vec<u8>,  // bucket che usano ogni pixel, margine compreso
+    pixels: Mutex<HashMap<u32, (PixelSamples, u8)>>,  // campioni e letture rimaste
+}
+
+impl BorderSamples {
+    pub fn new(rects: &[Rect], width: u32, height: u32, margin: u32) -> Self {
+        let mut uses = vec![0u8; (width as usize) * (height as usize)];
+        for rect in rects {
+            let area = rect.with_margin(margin, width, height);
+            for y in area.y..area.y + area.height {
+                for x in area.x..area.x + area.width {
+                    let index = (y * width + x) as usize;
+                    uses[index] = uses[index].saturating_add(1);
+                }
+            }
+        }
+        BorderSamples { width, uses, pixels: Mutex::new(HashMap::new()) }
+    }
+
+    // Campioni del pixel (x, y): `render` li calcola solo se nessun altro bucket l'ha già fatto.
+    // Il campionatore è deterministico, quindi due bucket che calcolano lo stesso pixel nello
+    // stesso momento ottengono gli stessi campioni.
+    pub fn get_or_render<F>(&self, x: u32, y: u32, render: F) -> PixelSamples
+    where
+        F: FnOnce() -> PixelSamples,
+    {
+        let index = y * self.width + x;
+        let uses = self.uses[index as usize];
+        if uses <= 1 {
+            return render();
+        }
+        if let Some(samples) = Self::take(&mut self.pixels.lock().unwrap(), index) {
+            return samples;
+        }
+        let samples = render();
+        let mut pixels = self.pixels.lock().unwrap();
+        if Self::take(&mut pixels, index).is_none() {
+            pixels.insert(index, (samples.clone(), uses - 1));
+        }
+        samples
+    }
+
+    fn take(pixels: &mut HashMap<u32, (PixelSamples, u8)>, index: u32) -> Option<PixelSamples> {
+        let (samples, remaining) = pixels.get_mut(&index)?;
+        *remaining -= 1;
+        if *remaining > 0 {
+            return Some(samples.clone());
+        }
+        pixels.remove(&index).map(|(samples, _)| samples)
+    }
+}
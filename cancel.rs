@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+
+// Controllo di un render in corso, condiviso fra chi lo avvia (interfaccia o gestore di
+// SIGINT) e i worker. I worker chiamano proceed() fra un bucket, un campione del render
+// progressivo o un pixel e il successivo: durante la pausa restano fermi lì, dopo cancel()
+// smettono di lavorare e il render restituisce l'immagine parziale.
+#[derive(Debug, Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    resumed: Condvar,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.cancelled.store(true, Ordering::Relaxed);
+        // Sveglia anche i worker in pausa, così possono uscire
+        self.resumed.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.paused.store(false, Ordering::Relaxed);
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // Attende finché il render è in pausa; false se il render è stato annullato
+    pub fn proceed(&self) -> bool {
+        if self.paused.load(Ordering::Relaxed) {
+            let mut guard = self.lock.lock().unwrap();
+            while self.paused.load(Ordering::Relaxed) && !self.is_cancelled() {
+                guard = self.resumed.wait(guard).unwrap();
+            }
+        }
+        !self.is_cancelled()
+    }
+}
@@ -0,0 +1,466 @@
+use crate::vector3::Vector3;
+use crate::baselight::AGColor;
+use crate::basecamera::BaseCamera;
+use crate::scene::Scene;
+use crate::scenesettings::SceneSettings;
+use crate::sampler::SamplerType;
+use crate::film::{Film, FilmSums};
+use crate::texture::Texture;
+use std::sync::Arc;
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Write};
+
+const MAGIC: &[u8; 4] = b"AGCK";
+const VERSION: u32 = 2;
+const HEADER_LENGTH: u64 = 24;  // magic, versione, hash della scena, larghezza, altezza
+const PIXEL_LENGTH: u64 = 36;  // campioni, colore e peso, somma semplice, numero di campioni
+
+// Stato di un render progressivo salvato su disco: le somme della pellicola e i campioni già
+// calcolati per ogni pixel. Il campionatore non ha stato proprio: ogni valore dipende solo da
+// tipo, seme, pixel e indice del campione, quindi i campioni per pixel bastano a ripartire
+// esattamente dal campione successivo. L'hash della scena evita di riprendere un render
+// con geometria, luci, camera o impostazioni diverse.
+pub struct Checkpoint {
+    pub scene_hash: u64,
+    pub width: u32,
+    pub height: u32,
+    pub sample_counts: Vec<u32>,
+    pub sums: FilmSums,
+}
+
+impl Checkpoint {
+    pub fn new(scene_hash: u64, film: &Film, sample_counts: &[u32]) -> Self {
+        Checkpoint {
+            scene_hash,
+            width: film.width,
+            height: film.height,
+            sample_counts: sample_counts.to_vec(),
+            sums: film.accumulation().clone(),
+        }
+    }
+
+    // Scrive prima in un file temporaneo e poi lo rinomina: un riavvio durante il salvataggio
+    // lascia intatto il checkpoint precedente
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        let temporary = format!("{}.tmp", filename);
+        {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+            writer.write_all(MAGIC)?;
+            writer.write_u32::<LittleEndian>(VERSION)?;
+            writer.write_u64::<LittleEndian>(self.scene_hash)?;
+            writer.write_u32::<LittleEndian>(self.width)?;
+            writer.write_u32::<LittleEndian>(self.height)?;
+            for &samples in &self.sample_counts {
+                writer.write_u32::<LittleEndian>(samples)?;
+            }
+            let sums = &self.sums;
+            for i in 0..sums.sum.len() {
+                write_color(&mut writer, &sums.sum[i])?;
+                writer.write_f32::<LittleEndian>(sums.weight_sum[i])?;
+                write_color(&mut writer, &sums.unweighted_sum[i])?;
+                writer.write_u32::<LittleEndian>(sums.sample_count[i])?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        std::fs::rename(&temporary, filename)
+    }
+
+    // Legge un checkpoint di un'immagine width x height: la risoluzione e la lunghezza del file
+    // vengono controllate prima di allocare, così un file troncato o di un altro render non
+    // porta a letture parziali o ad allocazioni enormi
+    pub fn load(filename: &str, width: u32, height: u32) -> Result<Checkpoint, Error> {
+        let file = File::open(filename)?;
+        let file_length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        std::io::Read::read_exact(&mut reader, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "non è un checkpoint di AGRay"));
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("versione del checkpoint {} non supportata", version)));
+        }
+        let scene_hash = reader.read_u64::<LittleEndian>()?;
+        let file_width = reader.read_u32::<LittleEndian>()?;
+        let file_height = reader.read_u32::<LittleEndian>()?;
+        if file_width != width || file_height != height {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("il checkpoint è di un'immagine {}x{}, non {}x{}", file_width, file_height, width, height),
+            ));
+        }
+        let num_pixels = width as u64 * height as u64;
+        // Intestazione, poi per ogni pixel i campioni (u32), colore e peso (4 f32),
+        // somma semplice dei colori (3 f32) e numero di campioni che lo raggiungono (u32)
+        if file_length != HEADER_LENGTH + num_pixels * PIXEL_LENGTH {
+            return Err(Error::new(ErrorKind::InvalidData, "lunghezza del checkpoint non valida"));
+        }
+        let num_pixels = num_pixels as usize;
+
+        let mut sample_counts = Vec::with_capacity(num_pixels);
+        for _ in 0..num_pixels {
+            sample_counts.push(reader.read_u32::<LittleEndian>()?);
+        }
+        let mut sums = FilmSums::new(num_pixels);
+        for i in 0..num_pixels {
+            sums.sum[i] = read_color(&mut reader)?;
+            sums.weight_sum[i] = reader.read_f32::<LittleEndian>()?;
+            sums.unweighted_sum[i] = read_color(&mut reader)?;
+            sums.sample_count[i] = reader.read_u32::<LittleEndian>()?;
+        }
+        Ok(Checkpoint { scene_hash, width, height, sample_counts, sums })
+    }
+}
+
+fn write_color(writer: &mut impl Write, color: &AGColor) -> Result<(), Error> {
+    writer.write_f32::<LittleEndian>(color.r)?;
+    writer.write_f32::<LittleEndian>(color.g)?;
+    writer.write_f32::<LittleEndian>(color.b)
+}
+
+fn read_color(reader: &mut impl std::io::Read) -> Result<AGColor, Error> {
+    let r = reader.read_f32::<LittleEndian>()?;
+    let g = reader.read_f32::<LittleEndian>()?;
+    let b = reader.read_f32::<LittleEndian>()?;
+    Ok(AGColor::new(r, g, b))
+}
+
+// Hash FNV-1a di tutto ciò che cambia il valore dei campioni. Non dipende da progressive_samples,
+// così un render ripreso può proseguire fino a un numero di campioni più alto, tranne che con
+// SamplerType::Stratified, i cui strati dipendono dal numero totale di campioni. Le texture
+// bitmap e la mappa d'ambiente sono identificate dal nome del file, non dal contenuto.
+pub fn scene_hash(camera: &BaseCamera, scene: &Scene, scene_settings: &SceneSettings) -> u64 {
+    let mut hasher = Fnv::new();
+
+    let obj = &scene.obj;
+    for point in &obj.padr {
+        hasher.vector(point);
+    }
+    for triangle in &obj.vadr {
+        hasher.u32(triangle.a as u32);
+        hasher.u32(triangle.b as u32);
+        hasher.u32(triangle.c as u32);
+    }
+    for vertex_data in [&obj.uvw, &obj.phong_normal] {
+        hasher.u32(vertex_data.is_some() as u32);
+        for value in vertex_data.iter().flatten() {
+            hasher.vector(value);
+        }
+    }
+    hasher.u32(obj.curvature.is_some() as u32);
+    for &value in obj.curvature.iter().flatten() {
+        hasher.f32(value);
+    }
+    hasher.u32(obj.material as u32);
+    hasher.u32(obj.tri_material.is_some() as u32);
+    for &material in obj.tri_material.iter().flatten() {
+        hasher.u32(material as u32);
+    }
+
+    for light in &scene.lights {
+        hasher.str(&light.name);
+        hasher.vector(&light.position);
+        hasher.vector(&light.direction);
+        hasher.color(&light.color);
+        for value in [light.intensity, light.spot_angle, light.inner_radius, light.radius_decay, light.area_size.0, light.area_size.1, light.angular_radius, light.reference_distance, light.max_range] {
+            hasher.f32(value);
+        }
+        hasher.str(&format!("{:?} {:?} {:?} {}", light.light_type, light.falloff, light.area_shape, light.casts_shadows));
+    }
+    for material in &scene.materials {
+        hasher.str(&material.name);
+        hasher.color(&material.base_color);
+        hasher.color(&material.absorption);
+        hasher.color(&material.emission);
+        for value in [material.metallic, material.roughness, material.specular, material.ior] {
+            hasher.f32(value);
+        }
+        hasher.str(&format!("{:?}", material.material_type));
+        for texture in [&material.base_color_texture, &material.roughness_texture, &material.normal_texture] {
+            hasher.texture(texture);
+        }
+    }
+    match &scene.environment {
+        Some(environment) => hasher.str(&environment.filename),
+        None => hasher.u32(0),
+    }
+
+    hasher.vector(&camera.position);
+    hasher.vector(&camera.target);
+    hasher.vector(&camera.up);
+    for value in [camera.fov, camera.aspect_ratio, camera._aperture, camera.focus_dist] {
+        hasher.f32(value);
+    }
+
+    hasher.str(&format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?}",
+        scene_settings.integrator, scene_settings.sampler, scene_settings.light_sampling,
+        scene_settings.ao_falloff, scene_settings.pixel_filter, scene_settings.mis_enabled,
+    ));
+    for value in [
+        scene_settings.max_depth, scene_settings.rr_start_depth, scene_settings.max_samples_ao,
+        scene_settings.max_samples_light, scene_settings.max_samples_env, scene_settings.max_samples_bsdf,
+        scene_settings.seed, scene_settings.ao_enabled as u32, scene_settings.shadows_enabled as u32,
+        scene_settings.env_visible as u32, scene_settings.shadow_mult as u32, scene_settings.ao_mult as u32,
+        scene_settings.sky_enabled as u32,
+    ] {
+        hasher.u32(value);
+    }
+    if scene_settings.sampler == SamplerType::Stratified {
+        hasher.u32(scene_settings.progressive_samples);
+    }
+    for value in [
+        scene_settings.ao_radius, scene_settings.env_intensity, scene_settings.env_rotation,
+        scene_settings.filter_radius, scene_settings.pixel_spread_angle, scene_settings.sun_elevation,
+        scene_settings.sun_azimuth, scene_settings.turbidity, scene_settings.sky_intensity,
+        scene_settings.sun_intensity,
+    ] {
+        hasher.f32(value);
+    }
+    hasher.finish()
+}
+
+// FNV-1a a 64 bit: a differenza di DefaultHasher il risultato non cambia fra versioni di Rust
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.write(value.as_bytes());
+        self.write(&[0]);
+    }
+
+    fn vector(&mut self, value: &Vector3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+
+    fn color(&mut self, value: &AGColor) {
+        self.f32(value.r);
+        self.f32(value.g);
+        self.f32(value.b);
+    }
+
+    // Parametri della texture; per le bitmap il file e il modo di campionamento
+    fn texture(&mut self, texture: &Option<Arc<Texture>>) {
+        let texture = match texture {
+            Some(texture) => texture,
+            None => return self.u32(0),
+        };
+        match texture.as_ref() {
+            Texture::Image(image) => {
+                self.str("Image");
+                self.str(&format!("{} {:?} {:?}", image.filename, image.wrap, image.filter));
+            },
+            Texture::Triplanar { image, scale, sharpness } => {
+                self.str("Triplanar");
+                self.str(&format!("{} {:?} {:?}", image.filename, image.wrap, image.filter));
+                self.f32(*scale);
+                self.f32(*sharpness);
+            },
+            Texture::Checker { color1, color2, scale } => {
+                self.str("Checker");
+                self.color(color1);
+                self.color(color2);
+                self.f32(*scale);
+            },
+            Texture::Noise { color1, color2, scale, octaves, lacunarity, gain } => {
+                self.str("Noise");
+                self.color(color1);
+                self.color(color2);
+                self.u32(*octaves);
+                for value in [*scale, *lacunarity, *gain] {
+                    self.f32(value);
+                }
+            },
+            Texture::Wood { color1, color2, scale, ring_frequency, distortion } => {
+                self.str("Wood");
+                self.color(color1);
+                self.color(color2);
+                for value in [*scale, *ring_frequency, *distortion] {
+                    self.f32(value);
+                }
+            },
+            Texture::Marble { color1, color2, scale, frequency, distortion } => {
+                self.str("Marble");
+                self.color(color1);
+                self.color(color2);
+                for value in [*scale, *frequency, *distortion] {
+                    self.f32(value);
+                }
+            },
+            Texture::EdgeWear { color1, color2, threshold, width, noise_scale } => {
+                self.str("EdgeWear");
+                self.color(color1);
+                self.color(color2);
+                for value in [*threshold, *width, *noise_scale] {
+                    self.f32(value);
+                }
+            },
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::baselight::{BaseLight, FalloffType, LightType};
+    use crate::film::{FilterType, PixelFilter};
+    use crate::mistest::test_scene;
+    use crate::environment::EnvironmentMap;
+
+    // Percorso nella cartella temporanea, diverso per ogni test
+    fn temporary_file(name: &str) -> String {
+        std::env::temp_dir().join(format!("agray_{}_{}.checkpoint", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn sample_checkpoint(scene_hash: u64) -> Checkpoint {
+        let mut film = Film::new(3, 2, PixelFilter::new(FilterType::Tent, 0.0));
+        film.add_sample(0.5, 0.5, AGColor::new(1.0, 0.5, 0.25));
+        film.add_sample(2.2, 1.7, AGColor::new(0.1, 0.2, 0.3));
+        Checkpoint::new(scene_hash, &film, &[1, 2, 3, 4, 5, 6])
+    }
+
+    #[test]
+    fn round_trip_keeps_everything() {
+        let filename = temporary_file("round_trip");
+        let saved = sample_checkpoint(42);
+        saved.save(&filename).unwrap();
+        let loaded = Checkpoint::load(&filename, 3, 2).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+
+        assert_eq!((loaded.scene_hash, loaded.width, loaded.height), (42, 3, 2));
+        assert_eq!(loaded.sample_counts, saved.sample_counts);
+        let colors = |sums: &[AGColor]| sums.iter().map(|c| (c.r, c.g, c.b)).collect::<Vec<_>>();
+        assert_eq!(colors(&loaded.sums.sum), colors(&saved.sums.sum));
+        assert_eq!(loaded.sums.weight_sum, saved.sums.weight_sum);
+        assert_eq!(colors(&loaded.sums.unweighted_sum), colors(&saved.sums.unweighted_sum));
+        assert_eq!(loaded.sums.sample_count, saved.sums.sample_count);
+    }
+
+    #[test]
+    fn wrong_resolution_is_rejected() {
+        let filename = temporary_file("wrong_resolution");
+        sample_checkpoint(42).save(&filename).unwrap();
+        let result = Checkpoint::load(&filename, 2, 3);
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let filename = temporary_file("truncated");
+        sample_checkpoint(42).save(&filename).unwrap();
+        let bytes = std::fs::read(&filename).unwrap();
+        std::fs::write(&filename, &bytes[..bytes.len() - 4]).unwrap();
+        let result = Checkpoint::load(&filename, 3, 2);
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(result.err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn checkpoint_of_another_scene_has_another_hash() {
+        let camera = test_camera();
+        let scene = test_scene(10.0, 0.3);
+        let scene_settings = SceneSettings::new();
+        let hash = scene_hash(&camera, &scene, &scene_settings);
+
+        let filename = temporary_file("wrong_hash");
+        sample_checkpoint(hash).save(&filename).unwrap();
+        let other_scene = test_scene(20.0, 0.3);
+        let loaded = Checkpoint::load(&filename, 3, 2).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(loaded.scene_hash, hash);
+        assert_ne!(loaded.scene_hash, scene_hash(&camera, &other_scene, &scene_settings));
+    }
+
+    fn test_camera() -> BaseCamera {
+        BaseCamera::new(
+            Vector3::new(0.0, 12.0, -60.0),
+            Vector3::new(0.0, 0.0, 15.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            16.0 / 9.0,
+            0.0,
+            1.0,
+        )
+    }
+
+    // Modifica di uno degli input dell'hash
+    type Change = fn(&mut BaseCamera, &mut Scene, &mut SceneSettings);
+
+    #[test]
+    fn scene_hash_sees_every_input() {
+        let changes: Vec<(&str, Change)> = vec![
+            ("vertice", |_, scene, _| scene.obj.padr[0].x += 1.0),
+            ("uv", |_, scene, _| scene.obj.uvw = Some(vec![Vector3::zero(); 4])),
+            ("curvatura", |_, scene, _| scene.obj.curvature = Some(vec![0.0; 4])),
+            ("materiale per triangolo", |_, scene, _| scene.obj.tri_material = Some(vec![0, 1])),
+            ("tipo di luce", |_, scene, _| scene.lights[0].light_type = LightType::Point),
+            ("decadimento", |_, scene, _| scene.lights[0].falloff = FalloffType::Quadratic),
+            ("distanza di riferimento", |_, scene, _| scene.lights[0].reference_distance = 10.0),
+            ("ombre della luce", |_, scene, _| scene.lights[0].casts_shadows = false),
+            ("nuova luce", |_, scene, _| scene.lights.push(BaseLight::new_directional("Sole".to_string(), Vector3::new(0.0, -1.0, 0.0), AGColor::new(1.0, 1.0, 1.0), 1.0, 0.5))),
+            ("roughness", |_, scene, _| scene.materials[0].roughness += 0.1),
+            ("texture procedurale", |_, scene, _| {
+                scene.materials[0].base_color_texture = Some(Arc::new(Texture::Checker { color1: AGColor::black(), color2: AGColor::new(1.0, 1.0, 1.0), scale: 1.0 }));
+            }),
+            ("ambiente", |_, scene, _| scene.environment = Some(EnvironmentMap::from_pixels("studio.hdr".to_string(), 2, 1, vec![AGColor::black(); 2]))),
+            ("camera", |camera, _, _| camera.fov += 1.0),
+            ("moltiplicatore ombre", |_, _, settings| settings.shadow_mult += 1),
+            ("moltiplicatore AO", |_, _, settings| settings.ao_mult += 1),
+            ("cielo", |_, _, settings| settings.sky_enabled = true),
+            ("elevazione del sole", |_, _, settings| settings.sun_elevation += 1.0),
+            ("azimut del sole", |_, _, settings| settings.sun_azimuth += 1.0),
+            ("torbidità", |_, _, settings| settings.turbidity += 1.0),
+            ("intensità del cielo", |_, _, settings| settings.sky_intensity += 1.0),
+            ("intensità del sole", |_, _, settings| settings.sun_intensity += 1.0),
+            ("seme", |_, _, settings| settings.seed += 1),
+        ];
+
+        let base = scene_hash(&test_camera(), &test_scene(10.0, 0.3), &SceneSettings::new());
+        let mut hashes = vec![base];
+        for (name, change) in changes {
+            let (mut camera, mut scene, mut scene_settings) = (test_camera(), test_scene(10.0, 0.3), SceneSettings::new());
+            change(&mut camera, &mut scene, &mut scene_settings);
+            let hash = scene_hash(&camera, &scene, &scene_settings);
+            assert!(!hashes.contains(&hash), "l'hash non cambia con: {}", name);
+            hashes.push(hash);
+        }
+
+        // progressive_samples conta solo per il campionatore stratificato
+        let mut scene_settings = SceneSettings::new();
+        scene_settings.progressive_samples *= 2;
+        assert_eq!(scene_hash(&test_camera(), &test_scene(10.0, 0.3), &scene_settings), base);
+        scene_settings.sampler = SamplerType::Stratified;
+        let stratified = scene_hash(&test_camera(), &test_scene(10.0, 0.3), &scene_settings);
+        scene_settings.progressive_samples *= 2;
+        assert_ne!(scene_hash(&test_camera(), &test_scene(10.0, 0.3), &scene_settings), stratified);
+    }
+}
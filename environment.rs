@@ -0,0 +1,143 @@
+use crate::vector3::Vector3;
+use crate::baselight::AGColor;
+use std::f32::consts::PI;
+
+// Mappa d'ambiente equirettangolare (.hdr / .exr) con Y verso l'alto.
+// Per l'importance sampling ogni pixel ha peso luminanza * sin(theta): si campiona
+// prima la riga con la CDF marginale e poi la colonna con la CDF condizionata.
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    pub filename: String,
+    width: usize,
+    height: usize,
+    pixels: Vec<AGColor>,
+    marginal_cdf: Vec<f32>,     // height + 1 valori
+    conditional_cdf: Vec<f32>,  // height righe da width + 1 valori
+}
+
+impl EnvironmentMap {
+    pub fn load(filename: &str) -> Result<EnvironmentMap, image::ImageError> {
+        let img = image::open(filename)?.to_rgb32f();
+        let (width, height) = img.dimensions();
+        let pixels = img.pixels().map(|p| AGColor::new(p[0], p[1], p[2])).collect();
+        Ok(EnvironmentMap::from_pixels(filename.to_string(), width as usize, height as usize, pixels))
+    }
+
+    pub fn from_pixels(filename: String, width: usize, height: usize, pixels: Vec<AGColor>) -> EnvironmentMap {
+        let mut conditional_cdf = Vec::with_capacity(height * (width + 1));
+        let mut marginal_cdf = Vec::with_capacity(height + 1);
+        marginal_cdf.push(0.0);
+
+        for y in 0..height {
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let row_start = conditional_cdf.len();
+            conditional_cdf.push(0.0);
+            let mut row_sum = 0.0;
+            for x in 0..width {
+                row_sum += pixels[y * width + x].luminance().max(0.0) * sin_theta;
+                conditional_cdf.push(row_sum);
+            }
+            // Normalizza la riga; una riga nera diventa uniforme
+            for i in 1..=width {
+                conditional_cdf[row_start + i] = if row_sum > 0.0 {
+                    conditional_cdf[row_start + i] / row_sum
+                } else {
+                    i as f32 / width as f32
+                };
+            }
+            let last = *marginal_cdf.last().unwrap();
+            marginal_cdf.push(last + row_sum);
+        }
+
+        let total = *marginal_cdf.last().unwrap();
+        for (i, value) in marginal_cdf.iter_mut().enumerate() {
+            *value = if total > 0.0 { *value / total } else { i as f32 / height as f32 };
+        }
+
+        EnvironmentMap {
+            filename,
+            width,
+            height,
+            pixels,
+            marginal_cdf,
+            conditional_cdf,
+        }
+    }
+
+    // Coordinate (u, v) in [0, 1) della direzione, con la mappa ruotata di `rotation` radianti attorno a Y
+    fn direction_to_uv(&self, dir: &Vector3, rotation: f32) -> (f32, f32) {
+        let phi = dir.x.atan2(-dir.z) + rotation;
+        let theta = dir.y.clamp(-1.0, 1.0).acos();
+        ((phi / (2.0 * PI)).rem_euclid(1.0), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f32, v: f32, rotation: f32) -> Vector3 {
+        let phi = u * 2.0 * PI - rotation;
+        let theta = v * PI;
+        Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+
+    fn texel(&self, x: usize, y: usize) -> AGColor {
+        self.pixels[y.min(self.height - 1) * self.width + x % self.width]
+    }
+
+    // Radianza in arrivo dalla direzione `dir` (bilineare)
+    pub fn lookup(&self, dir: &Vector3, rotation: f32) -> AGColor {
+        let (u, v) = self.direction_to_uv(dir, rotation);
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).max(0.0);
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let (tx, ty) = (x - x0, y - y0);
+        let x0 = x0.rem_euclid(self.width as f32) as usize;
+        let y0 = y0 as usize;
+
+        let c00 = self.texel(x0, y0);
+        let c10 = self.texel(x0 + 1, y0);
+        let c01 = self.texel(x0, y0 + 1);
+        let c11 = self.texel(x0 + 1, y0 + 1);
+        (c00 * (1.0 - tx) + c10 * tx) * (1.0 - ty) + (c01 * (1.0 - tx) + c11 * tx) * ty
+    }
+
+    // Indice dell'intervallo della CDF che contiene u e posizione relativa al suo interno
+    fn sample_cdf(cdf: &[f32], u: f32) -> (usize, f32) {
+        let count = cdf.len() - 1;
+        let index = cdf.partition_point(|&c| c <= u).saturating_sub(1).min(count - 1);
+        let width = cdf[index + 1] - cdf[index];
+        let offset = if width > 0.0 { ((u - cdf[index]) / width).clamp(0.0, 1.0) } else { 0.5 };
+        (index, offset)
+    }
+
+    // Campiona una direzione proporzionalmente alla luminanza.
+    // Restituisce direzione, radianza e pdf in angolo solido.
+    pub fn sample(&self, u1: f32, u2: f32, rotation: f32) -> Option<(Vector3, AGColor, f32)> {
+        let (y, dy) = Self::sample_cdf(&self.marginal_cdf, u1);
+        let row = &self.conditional_cdf[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let (x, dx) = Self::sample_cdf(row, u2);
+
+        let u = (x as f32 + dx) / self.width as f32;
+        let v = (y as f32 + dy) / self.height as f32;
+        let dir = self.uv_to_direction(u, v, rotation);
+        let pdf = self.pdf(&dir, rotation);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((dir, self.lookup(&dir, rotation), pdf))
+    }
+
+    // Pdf in angolo solido con cui sample() genera la direzione `dir`
+    pub fn pdf(&self, dir: &Vector3, rotation: f32) -> f32 {
+        let (u, v) = self.direction_to_uv(dir, rotation);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        let sin_theta = (PI * (y as f32 + 0.5) / self.height as f32).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let row = y * (self.width + 1);
+        let p_row = self.marginal_cdf[y + 1] - self.marginal_cdf[y];
+        let p_col = self.conditional_cdf[row + x + 1] - self.conditional_cdf[row + x];
+        // Da probabilità discreta del pixel a densità su (u, v), poi in angolo solido
+        p_row * p_col * (self.width * self.height) as f32 / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -0,0 +1,249 @@
+use crate::baselight::AGColor;
+use std::f32::consts::PI;
+
+// Filtro di ricostruzione con cui i campioni vengono distribuiti sui pixel vicini
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    Box,             // media dei campioni che cadono entro il raggio (raggio 0.5 = solo il pixel)
+    Tent,            // triangolare: peso che scende linearmente fino al raggio
+    Gaussian,        // gaussiana con sigma = raggio / 3, traslata per annullarsi al raggio
+    Mitchell,        // Mitchell-Netravali con B = C = 1/3: più nitido, con lobi negativi
+    BlackmanHarris,  // finestra di Blackman-Harris a 4 termini: morbido, senza lobi negativi
+}
+
+impl FilterType {
+    // Raggio in pixel usato quando SceneSettings::filter_radius è 0
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.0,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell => 2.0,
+            FilterType::BlackmanHarris => 1.5,
+        }
+    }
+}
+
+// Filtro separabile: il peso di un campione a distanza (dx, dy) dal centro del pixel è f(dx) * f(dy)
+#[derive(Debug, Clone, Copy)]
+pub struct PixelFilter {
+    pub filter_type: FilterType,
+    pub radius: f32,
+}
+
+impl PixelFilter {
+    pub fn new(filter_type: FilterType, radius: f32) -> Self {
+        let radius = if radius > 0.0 { radius } else { filter_type.default_radius() };
+        PixelFilter { filter_type, radius }
+    }
+
+    // Pixel oltre il bordo di una regione i cui campioni raggiungono ancora la regione
+    pub fn margin(&self) -> u32 {
+        (self.radius - 0.5).ceil().max(0.0) as u32
+    }
+
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+        match self.filter_type {
+            FilterType::Box => 1.0,
+            FilterType::Tent => self.radius - x,
+            FilterType::Gaussian => {
+                let sigma = self.radius / 3.0;
+                let gaussian = |d: f32| (-d * d / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(self.radius)).max(0.0)
+            },
+            FilterType::Mitchell => mitchell(2.0 * x / self.radius, 1.0 / 3.0, 1.0 / 3.0),
+            FilterType::BlackmanHarris => {
+                let t = 0.5 + 0.5 * x / self.radius;
+                0.35875 - 0.48829 * (2.0 * PI * t).cos() + 0.14128 * (4.0 * PI * t).cos() - 0.01168 * (6.0 * PI * t).cos()
+            },
+        }
+    }
+}
+
+// Polinomio cubico di Mitchell-Netravali su [0, 2]
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+// Somme accumulate da una pellicola, per pixel: campioni pesati dal filtro e somma dei pesi,
+// più la media semplice dei campioni che raggiungono il pixel. Quest'ultima serve solo quando
+// i lobi negativi del filtro portano la somma dei pesi a zero o sotto.
+#[derive(Debug, Clone)]
+pub struct FilmSums {
+    pub sum: Vec<AGColor>,
+    pub weight_sum: Vec<f32>,
+    pub unweighted_sum: Vec<AGColor>,
+    pub sample_count: Vec<u32>,
+}
+
+impl FilmSums {
+    pub fn new(num_pixels: usize) -> Self {
+        FilmSums {
+            sum: vec![AGColor::black(); num_pixels],
+            weight_sum: vec![0.0; num_pixels],
+            unweighted_sum: vec![AGColor::black(); num_pixels],
+            sample_count: vec![0; num_pixels],
+        }
+    }
+}
+
+// Pellicola: per ogni pixel la somma dei campioni pesati dal filtro e la somma dei pesi.
+// Un campione in posizione continua (x, y), con il pixel (px, py) che copre [px, px + 1),
+// contribuisce a tutti i pixel il cui centro dista meno del raggio del filtro.
+#[derive(Debug, Clone)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: PixelFilter,
+    sums: FilmSums,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: PixelFilter) -> Self {
+        Film {
+            width,
+            height,
+            filter,
+            sums: FilmSums::new((width * height) as usize),
+        }
+    }
+
+    pub fn add_sample(&mut self, x: f32, y: f32, color: AGColor) {
+        // Coordinate discrete: il centro del pixel (px, py) è in (px, py)
+        let (dx, dy) = (x - 0.5, y - 0.5);
+        let radius = self.filter.radius;
+        let x0 = (dx - radius).ceil().max(0.0) as u32;
+        let y0 = (dy - radius).ceil().max(0.0) as u32;
+        let x1 = (dx + radius).floor().min(self.width as f32 - 1.0);
+        let y1 = (dy + radius).floor().min(self.height as f32 - 1.0);
+        if x1 < 0.0 || y1 < 0.0 {
+            return;
+        }
+        for py in y0..=y1 as u32 {
+            for px in x0..=x1 as u32 {
+                let weight = self.filter.evaluate(px as f32 - dx, py as f32 - dy);
+                if weight != 0.0 {
+                    let index = (py * self.width + px) as usize;
+                    self.sums.sum[index] += color * weight;
+                    self.sums.weight_sum[index] += weight;
+                    self.sums.unweighted_sum[index] += color;
+                    self.sums.sample_count[index] += 1;
+                }
+            }
+        }
+    }
+
+    // Somme accumulate, per salvarle in un checkpoint
+    pub fn accumulation(&self) -> &FilmSums {
+        &self.sums
+    }
+
+    // Riprende le somme di un checkpoint con la stessa risoluzione
+    pub fn restore(&mut self, sums: FilmSums) {
+        if sums.sum.len() == self.sums.sum.len() {
+            self.sums = sums;
+        }
+    }
+
+    // Colore ricostruito del pixel; nero se nessun campione lo raggiunge
+    pub fn pixel(&self, x: u32, y: u32) -> AGColor {
+        let index = (y * self.width + x) as usize;
+        let weight = self.sums.weight_sum[index];
+        let count = self.sums.sample_count[index];
+        let color = if weight > 0.0 {
+            self.sums.sum[index] * (1.0 / weight)
+        } else if count > 0 {
+            // Solo campioni nei lobi negativi: la media pesata non ha senso, si usa quella semplice
+            self.sums.unweighted_sum[index] * (1.0 / count as f32)
+        } else {
+            return AGColor::black();
+        };
+        // I lobi negativi di Mitchell possono portare sotto zero vicino ai bordi netti
+        AGColor::new(color.r.max(0.0), color.g.max(0.0), color.b.max(0.0))
+    }
+
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let color = self.pixel(x, y);
+                [(color.r * 255.0) as u8, (color.g * 255.0) as u8, (color.b * 255.0) as u8, 255]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_FILTERS: [FilterType; 5] = [FilterType::Box, FilterType::Tent, FilterType::Gaussian, FilterType::Mitchell, FilterType::BlackmanHarris];
+
+    #[test]
+    fn filters_peak_at_center_and_end_at_radius() {
+        for filter_type in ALL_FILTERS {
+            let filter = PixelFilter::new(filter_type, 0.0);
+            let center = filter.evaluate(0.0, 0.0);
+            assert!(center > 0.0, "{:?}", filter_type);
+            for step in 1..20 {
+                let x = filter.radius * step as f32 / 20.0;
+                assert!(filter.evaluate(x, 0.0) <= center, "{:?} in {}", filter_type, x);
+                assert_eq!(filter.evaluate(x, 0.0), filter.evaluate(-x, 0.0));
+            }
+            // Solo il box resta pieno fino al raggio
+            if filter_type != FilterType::Box {
+                assert!(filter.evaluate(filter.radius, 0.0).abs() < 1e-4, "{:?}", filter_type);
+            }
+            assert_eq!(filter.evaluate(filter.radius + 0.01, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn filter_weights_match_their_shape() {
+        let box_filter = PixelFilter::new(FilterType::Box, 0.0);
+        assert_eq!(box_filter.evaluate(0.4, -0.3), 1.0);
+
+        let tent = PixelFilter::new(FilterType::Tent, 2.0);
+        assert!((tent.evaluate(0.5, 0.0) / tent.evaluate(0.0, 0.0) - 0.75).abs() < 1e-6);
+        assert!((tent.evaluate(1.0, 1.0) - 1.0).abs() < 1e-6);
+
+        // Mitchell ha un lobo negativo fra metà raggio e il raggio
+        let mitchell = PixelFilter::new(FilterType::Mitchell, 2.0);
+        assert!(mitchell.evaluate(1.5, 0.0) < 0.0);
+
+        assert!(PixelFilter::new(FilterType::BlackmanHarris, 0.0).evaluate(1.0, 0.0) >= 0.0);
+    }
+
+    #[test]
+    fn pixel_without_positive_weight_uses_plain_average() {
+        // Un campione a 1.5 pixel di distanza cade nel lobo negativo di Mitchell
+        let mut film = Film::new(8, 1, PixelFilter::new(FilterType::Mitchell, 2.0));
+        film.add_sample(2.0, 0.5, AGColor::new(0.5, 0.25, 1.0));
+        assert!(film.accumulation().weight_sum[0] < 0.0);
+        let color = film.pixel(0, 0);
+        assert_eq!((color.r, color.g, color.b), (0.5, 0.25, 1.0));
+        let color = film.pixel(7, 0);
+        assert_eq!((color.r, color.g, color.b), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn margin_covers_filter_radius() {
+        assert_eq!(PixelFilter::new(FilterType::Box, 0.5).margin(), 0);
+        assert_eq!(PixelFilter::new(FilterType::Tent, 1.0).margin(), 1);
+        assert_eq!(PixelFilter::new(FilterType::Mitchell, 2.0).margin(), 2);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::vector3::Vector3;
+use crate::boundingbox::Boundingbox;
+use crate::baselight::{BaseLight, FalloffType, LightType};
+use crate::meshlight::MeshLight;
+use std::f32::consts::PI;
+
+// Riferimento a una sorgente di luce della scena
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightRef {
+    Light(usize),  // indice in Scene::lights
+    Mesh(usize),   // indice in Scene::mesh_lights
+}
+
+// Stima grossolana di quanto una sorgente può contribuire in un punto, divisa in una parte
+// che non dipende dalla distanza (luci senza decadimento, come in Cinema 4D) e una che
+// decresce con il quadrato della distanza.
+#[derive(Debug, Clone, Copy)]
+struct LightPower {
+    flat: f32,
+    decaying: f32,
+}
+
+#[derive(Debug)]
+struct LightNode {
+    bbox: Boundingbox,
+    radius: f32,        // metà della diagonale del box
+    power: LightPower,
+    kind: LightNodeKind,
+    parent: Option<usize>,
+}
+
+#[derive(Debug)]
+enum LightNodeKind {
+    Leaf(LightRef),
+    Interior(usize, usize),  // indici dei figli in LightTree::nodes
+}
+
+// BVH delle luci finite (Point, Spot, Area e oggetti emissivi). Per ogni campione si
+// scende dalla radice scegliendo un figlio in proporzione alla sua importanza stimata,
+// così il costo della luce diretta dipende dal numero di campioni e non dal numero di luci.
+// Le luci all'infinito (Directional) e l'ambiente restano fuori dall'albero.
+#[derive(Debug)]
+pub struct LightTree {
+    nodes: Vec<LightNode>,
+    leaves: Vec<(LightRef, usize)>,  // foglia di ogni sorgente, per pmf()
+}
+
+impl LightTree {
+    pub fn build(lights: &[BaseLight], mesh_lights: &[MeshLight]) -> Option<LightTree> {
+        let mut leaves = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            if light.is_infinite() {
+                continue;
+            }
+            let radius = light_radius(light);
+            let extent = Vector3::new(radius, radius, radius);
+            let bbox = Boundingbox::new(light.position - extent, light.position + extent);
+            let strength = light.color.luminance() * light.intensity * PI;
+            let power = match light.falloff {
+                FalloffType::Quadratic => {
+                    let reference = light.reference_distance;
+                    LightPower { flat: 0.0, decaying: strength * reference * reference }
+                },
+                // Il decadimento lineare 1/d è trattato come costante: sovrastima le luci lontane
+                _ => LightPower { flat: strength, decaying: 0.0 },
+            };
+            leaves.push(LightNode { radius, bbox, power, kind: LightNodeKind::Leaf(LightRef::Light(index)), parent: None });
+        }
+        for (index, mesh_light) in mesh_lights.iter().enumerate() {
+            leaves.push(LightNode {
+                bbox: mesh_light.bbox.clone(),
+                radius: mesh_light.bbox.diagonal() * 0.5,
+                power: LightPower { flat: 0.0, decaying: mesh_light.power() },
+                kind: LightNodeKind::Leaf(LightRef::Mesh(index)),
+                parent: None,
+            });
+        }
+        if leaves.is_empty() {
+            return None;
+        }
+
+        let mut tree = LightTree { nodes: Vec::with_capacity(leaves.len() * 2), leaves: Vec::new() };
+        tree.build_recursive(leaves);
+        tree.leaves = tree.nodes.iter().enumerate()
+            .filter_map(|(index, node)| match node.kind {
+                LightNodeKind::Leaf(light) => Some((light, index)),
+                LightNodeKind::Interior(..) => None,
+            })
+            .collect();
+        Some(tree)
+    }
+
+    // Costruisce il sottoalbero e ne restituisce l'indice; la radice finisce in posizione 0
+    fn build_recursive(&mut self, mut leaves: Vec<LightNode>) -> usize {
+        if leaves.len() == 1 {
+            let node = leaves.pop().unwrap();
+            self.nodes.push(node);
+            return self.nodes.len() - 1;
+        }
+
+        let mut bbox = Boundingbox::new_empty();
+        let mut centers = Boundingbox::new_empty();
+        let mut power = LightPower { flat: 0.0, decaying: 0.0 };
+        for leaf in &leaves {
+            bbox.expand(&leaf.bbox);
+            centers.expand(&Boundingbox::new(leaf.bbox.center, leaf.bbox.center));
+            power.flat += leaf.power.flat;
+            power.decaying += leaf.power.decaying;
+        }
+
+        // Divisione a metà lungo l'asse più esteso dei centri
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        let key = |node: &LightNode| match axis {
+            0 => node.bbox.center.x,
+            1 => node.bbox.center.y,
+            _ => node.bbox.center.z,
+        };
+        leaves.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(std::cmp::Ordering::Equal));
+        let right_leaves = leaves.split_off(leaves.len() / 2);
+
+        let index = self.nodes.len();
+        let radius = bbox.diagonal() * 0.5;
+        self.nodes.push(LightNode { bbox, radius, power, kind: LightNodeKind::Interior(0, 0), parent: None });
+        let left = self.build_recursive(leaves);
+        let right = self.build_recursive(right_leaves);
+        self.nodes[index].kind = LightNodeKind::Interior(left, right);
+        self.nodes[left].parent = Some(index);
+        self.nodes[right].parent = Some(index);
+        index
+    }
+
+    fn importance(&self, node: &LightNode, point: &Vector3, lights: &[BaseLight]) -> f32 {
+        let distance_squared = (node.bbox.center - *point).length_squared();
+        match node.kind {
+            // Per le luci si usa il falloff vero, misurato dal punto più vicino della luce
+            LightNodeKind::Leaf(LightRef::Light(index)) => {
+                let light = &lights[index];
+                let distance = (distance_squared.sqrt() - node.radius).max(0.0);
+                let cone = match light.light_type {
+                    LightType::Spot => light.spot_attenuation(&(*point - light.position).normalize()),
+                    _ => 1.0,
+                };
+                (node.power.flat + node.power.decaying) * light.attenuation(distance) * cone
+            },
+            _ => node.power.flat + node.power.decaying / distance_squared.max(node.radius * node.radius).max(1e-8),
+        }
+    }
+
+    // Sceglie una sorgente per il punto con u in [0, 1); restituisce anche la sua probabilità
+    pub fn sample(&self, point: &Vector3, lights: &[BaseLight], u: f32) -> Option<(LightRef, f32)> {
+        let mut index = 0;
+        let mut u = u;
+        let mut pmf = 1.0;
+        loop {
+            match self.nodes[index].kind {
+                LightNodeKind::Leaf(light) => {
+                    return if pmf > 0.0 { Some((light, pmf)) } else { None };
+                },
+                LightNodeKind::Interior(left, right) => {
+                    let left_importance = self.importance(&self.nodes[left], point, lights);
+                    let right_importance = self.importance(&self.nodes[right], point, lights);
+                    let total = left_importance + right_importance;
+                    if total <= 0.0 {
+                        return None;
+                    }
+                    let p_left = left_importance / total;
+                    // u viene riscalato nell'intervallo scelto e riusato al livello successivo
+                    if u < p_left {
+                        u = (u / p_left).min(1.0 - f32::EPSILON);
+                        pmf *= p_left;
+                        index = left;
+                    } else {
+                        u = ((u - p_left) / (1.0 - p_left)).min(1.0 - f32::EPSILON);
+                        pmf *= 1.0 - p_left;
+                        index = right;
+                    }
+                },
+            }
+        }
+    }
+
+    // Probabilità con cui sample() sceglierebbe `light` dal punto, risalendo dalla foglia alla radice
+    pub fn pmf(&self, point: &Vector3, lights: &[BaseLight], light: LightRef) -> f32 {
+        let mut index = match self.leaves.iter().find(|(leaf, _)| *leaf == light) {
+            Some(&(_, index)) => index,
+            None => return 0.0,
+        };
+        let mut pmf = 1.0;
+        while let Some(parent) = self.nodes[index].parent {
+            if let LightNodeKind::Interior(left, right) = self.nodes[parent].kind {
+                let left_importance = self.importance(&self.nodes[left], point, lights);
+                let right_importance = self.importance(&self.nodes[right], point, lights);
+                let total = left_importance + right_importance;
+                if total <= 0.0 {
+                    return 0.0;
+                }
+                pmf *= if index == left { left_importance } else { right_importance } / total;
+            }
+            index = parent;
+        }
+        pmf
+    }
+}
+
+// Raggio della sfera che contiene la luce
+fn light_radius(light: &BaseLight) -> f32 {
+    match light.light_type {
+        LightType::Area => {
+            let (width, height) = light.area_size;
+            (width * width + height * height).sqrt() * 0.5
+        },
+        _ => 0.0,
+    }
+}
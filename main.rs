@@ -8,17 +8,33 @@ mod scenesettings;
 mod baselight;
 mod basecamera;
 mod bucket;
+mod basematerial;
+mod pathtracer;
+mod texture;
+mod noise;
+mod scene;
+mod environment;
+mod sky;
+mod meshlight;
+mod lighttree;
+mod mistest;
+mod sampler;
+mod film;
+mod progressive;
+mod renderjob;
+mod cancel;
+mod checkpoint;
 
 use crossbeam::thread;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use crate::bucket::BucketState;
-use crate::bucket::{Bucket, Rect};
+use crate::bucket::{Bucket, BorderSamples, Rect};
 use iced::{
     Color,
     container, Background, Element, Length, 
     Application, Button, Column, Command, Container, 
-    Image, Settings, Slider, Text, button, slider, 
+    Image, Row, Settings, Slider, Subscription, Text, button, slider, 
     image::Handle
 };
 
@@ -28,14 +44,26 @@ use crate::basecamera::BaseCamera;
 use crate::baselight::AGColor;
 use crate::baselight::LightType;
 use crate::baselight::FalloffType;
-use baseobject::BaseObject;
-use image::{ImageBuffer, Rgba};
+use baseobject::{BaseObject, SurfaceHit};
+use std::borrow::Cow;
+use crate::scene::{Scene, RenderContext};
+use crate::meshlight::MeshLight;
+use crate::lighttree::LightRef;
+use crate::sampler::Sampler;
+use crate::film::{Film, PixelFilter};
+use crate::renderjob::RenderJob;
+use crate::progressive::render_passes;
+use crate::cancel::CancelToken;
+use crate::environment::EnvironmentMap;
+use crate::sky::SkyModel;
 use vector3::Vector3;
 use std::time::Instant;
-use rand::random;
-use crate::scenesettings::SceneSettings;
+use crate::scenesettings::{SceneSettings, IntegratorType, AoFalloff, RenderMode, BucketOrder};
+use crate::pathtracer::trace_path;
 use crate::baselight::BaseLight;
 use crate::baseray::BaseRay;
+use crate::basematerial::{BaseMaterial, MaterialType};
+use std::f32::consts::PI;
 
 use rayon::prelude::*;
 // use std::sync::Arc;
@@ -44,11 +72,33 @@ const SCREEN_WIDTH: u32 = 1080;
 const SCREEN_HEIGHT: u32 = 1080;
 
 fn main() -> iced::Result {
+    // Confronto della varianza con e senza MIS, senza interfaccia
+    if std::env::args().any(|arg| arg == "--mis-test") {
+        mistest::run_mis_comparison();
+        return Ok(());
+    }
+    // Render bloccante senza interfaccia, nella modalità di SceneSettings::render_mode
+    if std::env::args().any(|arg| arg == "--headless") {
+        setup_scene();
+        return Ok(());
+    }
     RustRender::run(Settings::default())
 }
+
+// --resume: il render progressivo riparte da SceneSettings::checkpoint_file.
+// Il render a bucket non salva checkpoint e lo ignora.
+fn resume_requested() -> bool {
+    std::env::args().any(|arg| arg == "--resume")
+}
+const NUM_THREADS: usize = 31;
+
 struct RustRender {
     render_image: Handle,
+    render: Option<RenderJob>,  // render in corso
+    status: String,
     generate_button: button::State,
+    stop_button: button::State,
+    pause_button: button::State,
     slider: slider::State,
     slider_value: f32,
 }
@@ -56,6 +106,9 @@ struct RustRender {
 #[derive(Debug, Clone)]
 enum Message {
     Generate,
+    Stop,
+    TogglePause,
+    Tick,
     SliderChanged(f32),
 }
 
@@ -79,11 +132,15 @@ impl Application for RustRender {
     type Flags = ();
 
     fn new(_flags: ()) -> (RustRender, Command<Self::Message>) {
-        let render_image = setup_scene();
         (
             RustRender {
-                render_image: Handle::from_pixels(SCREEN_WIDTH, SCREEN_HEIGHT, render_image),
+                render_image: Handle::from_pixels(SCREEN_WIDTH, SCREEN_HEIGHT, vec![0; (SCREEN_WIDTH * SCREEN_HEIGHT * 4) as usize]),
+                // Con --resume il primo render riparte dall'ultimo checkpoint
+                render: Some(RenderJob::start(SCREEN_WIDTH, SCREEN_HEIGHT, NUM_THREADS, resume_requested(), None, load_scene)),
+                status: "Caricamento della scena...".to_string(),
                 generate_button: button::State::new(),
+                stop_button: button::State::new(),
+                pause_button: button::State::new(),
                 slider: slider::State::new(),
                 slider_value: 50.0, // valore iniziale dello slider
             },
@@ -98,8 +155,44 @@ impl Application for RustRender {
     fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
         match message {
             Message::Generate => {
-                let render_image = setup_scene();
-                self.render_image = Handle::from_pixels(SCREEN_WIDTH, SCREEN_HEIGHT, render_image);
+                // Un nuovo render sostituisce quello in corso: il suo thread attende che il
+                // precedente si fermi, così l'interfaccia non si blocca
+                let previous = self.render.take();
+                self.status = match &previous {
+                    Some(render) => {
+                        render.stop();
+                        "Interruzione del render precedente...".to_string()
+                    },
+                    None => "Caricamento della scena...".to_string(),
+                };
+                self.render = Some(RenderJob::start(SCREEN_WIDTH, SCREEN_HEIGHT, NUM_THREADS, false, previous, load_scene));
+            },
+            Message::Stop => {
+                if let Some(render) = &self.render {
+                    render.stop();
+                    self.status = "Interruzione del render...".to_string();
+                }
+            },
+            Message::TogglePause => {
+                if let Some(render) = &self.render {
+                    if render.is_paused() {
+                        render.resume();
+                    } else {
+                        render.pause();
+                    }
+                }
+            },
+            Message::Tick => {
+                if let Some(render) = &self.render {
+                    let (update, finished) = render.poll();
+                    if let Some((image, status)) = update {
+                        self.render_image = Handle::from_pixels(SCREEN_WIDTH, SCREEN_HEIGHT, image);
+                        self.status = status;
+                    }
+                    if finished {
+                        self.render = None;
+                    }
+                }
             },
             Message::SliderChanged(value) => {
                 self.slider_value = value;
@@ -107,12 +200,26 @@ impl Application for RustRender {
         }
         Command::none()
     }
+
+    // Finché c'è un render in corso l'interfaccia controlla periodicamente se l'immagine è cambiata
+    fn subscription(&self) -> Subscription<Self::Message> {
+        if self.render.is_some() {
+            iced::time::every(std::time::Duration::from_millis(200)).map(|_| Message::Tick)
+        } else {
+            Subscription::none()
+        }
+    }
     
 
     fn view(&mut self) -> Element<'_, Self::Message> {
         // Definizione del bottone
         let button = Button::new(&mut self.generate_button, Text::new("Generate"))
             .on_press(Message::Generate);
+        let stop_button = Button::new(&mut self.stop_button, Text::new("Stop"))
+            .on_press(Message::Stop);
+        let paused = self.render.as_ref().map_or(false, |render| render.is_paused());
+        let pause_button = Button::new(&mut self.pause_button, Text::new(if paused { "Resume" } else { "Pause" }))
+            .on_press(Message::TogglePause);
     
         // Definizione dello slider
         let slider = Slider::new(
@@ -126,7 +233,8 @@ impl Application for RustRender {
         let content = Column::new()
             .spacing(10)
             .push(Image::new(self.render_image.clone()).width(Length::Fill).height(Length::Fill))
-            .push(button)
+            .push(Row::new().spacing(10).push(button).push(pause_button).push(stop_button))
+            .push(Text::new(self.status.clone()))
             .push(slider);
     
         // Container che ingloba il contenuto
@@ -140,96 +248,74 @@ impl Application for RustRender {
     }        
 }
 
-fn setup_scene() -> Vec<u8> {
-    let (width, height) = (SCREEN_WIDTH, SCREEN_HEIGHT);
-    let imgbuf = ImageBuffer::<Rgba<u8>, Vec<u8>>::new(width, height);
-
+// Carica oggetto, luci, materiali, camera e ambiente della scena
+pub fn load_scene() -> (BaseCamera, Scene, SceneSettings) {
     // -------------------------------------------------------------------------------------------------------------------------
 
     // Init Lights ----------------------------------------------------
-    let luce1 = BaseLight::new(
+    let mut luce1 = BaseLight::new(
         "Light.2_Spot".to_string(),
         Vector3::new(-259.95, 518.74, 310.19),
         Vector3::new(0.39509776, -0.7884324, -0.47145748),
         AGColor::new(0.424, 0.536, 0.851),
         1.254,
         LightType::Spot,
-        FalloffType::None,
-        28.8,
-        0.0,
-        0.0,
-        (72.83, 72.83),
     );
+    luce1.spot_angle = 28.8;
+    luce1.area_size = (72.83, 72.83);
     
-    let luce2 = BaseLight::new(
+    let mut luce2 = BaseLight::new(
         "Light.6".to_string(),
         Vector3::new(-355.24, -47.73, -221.27),
         Vector3::new(0.84334135, 0.11331124, 0.525296),
         AGColor::new(0.98, 0.96, 0.94),
         0.582,
         LightType::Spot,
-        FalloffType::Linear,
-        28.8,
-        0.0,
-        518.0,
-        (40.068222, 38.55295),
     );
+    luce2.falloff = FalloffType::Linear;
+    luce2.spot_angle = 28.8;
+    luce2.radius_decay = 518.0;
+    luce2.area_size = (40.068222, 38.55295);
     
-    let luce3 = BaseLight::new(
+    let mut luce3 = BaseLight::new(
         "Light.4".to_string(),
         Vector3::new(248.92, 322.63, 227.43),
         Vector3::new(-0.53340256, -0.6913534, -0.48735234),
         AGColor::new(0.567, 0.797, 1.042),
         1.088,
         LightType::Area,
-        FalloffType::None,
-        0.0,
-        0.0,
-        0.0,
-        (72.83, 72.83),
     );
+    luce3.area_size = (72.83, 72.83);
     
-    let luce4 = BaseLight::new(
+    let mut luce4 = BaseLight::new(
         "Light.1".to_string(),
         Vector3::new(284.31, 297.96, -348.31),
         Vector3::new(-0.5271039, -0.55241066, 0.6457584),
         AGColor::new(0.98, 0.96, 0.94),
         0.567,
         LightType::Area,
-        FalloffType::None,
-        0.0,
-        0.0,
-        0.0,
-        (72.83, 72.83),
     );
+    luce4.area_size = (72.83, 72.83);
     
-    let luce5 = BaseLight::new(
+    let mut luce5 = BaseLight::new(
         "Light".to_string(),
         Vector3::new(195.36, -171.45, -294.36),
         Vector3::new(-0.4974868, 0.43659967, 0.74959165),
         AGColor::new(1.0, 0.336, 0.084),
         0.199,
         LightType::Area,
-        FalloffType::None,
-        0.0,
-        0.0,
-        0.0,
-        (72.83, 72.83),
     );
+    luce5.area_size = (72.83, 72.83);
     
-    let luce6 = BaseLight::new(
+    let mut luce6 = BaseLight::new(
         "Light.5".to_string(),
         Vector3::new(-320.85, 120.83, -300.78),
         Vector3::new(0.7034878, -0.26492888, 0.6594828),
         AGColor::new(0.98, 0.96, 0.94),
         0.12,
         LightType::Area,
-        FalloffType::None,
-        0.0,
-        0.0,
-        0.0,
-        (72.83, 72.83),
-    ); 
+    );
+    luce6.area_size = (72.83, 72.83); 
     // end of Init Lights ----------------------------------------------   
 
     // add lights to Vec. ----------------------------------------------
@@ -242,6 +328,11 @@ fn setup_scene() -> Vec<u8> {
     lights.push(luce6);    
     // ------------------------------------------------------------------
 
+    // Init Materials --------------------------------------------------
+    let mut materials = Vec::new();
+    materials.push(BaseMaterial::default_material());
+    // ------------------------------------------------------------------
+
     // Init Camera -----------------------------------------------------
     let mut camera = BaseCamera::new(
         Vector3::new(20.0, 20.0, -100.),
@@ -253,16 +344,9 @@ fn setup_scene() -> Vec<u8> {
         1000.0,
     );
 
-    let scene_settings = SceneSettings::new();
-
-    // Generate Buckets ------------------------------------------------
-    let rect = Rect {
-        x: 0,
-        y: 0,
-        width: SCREEN_WIDTH,
-        height: SCREEN_HEIGHT,
-    };
-    let buckets = generate_buckets(SCREEN_WIDTH, SCREEN_HEIGHT, rect, scene_settings.bucket_count);
+    let mut scene_settings = SceneSettings::new();
+    // Apertura angolare di un pixel, per la scelta del livello di mip-map delle texture
+    scene_settings.pixel_spread_angle = camera.fov.to_radians() / SCREEN_HEIGHT as f32;
 
     println!("Iniziando il caricamento del file STL...");
     let start_load = Instant::now();
@@ -274,385 +358,760 @@ fn setup_scene() -> Vec<u8> {
     obj.build_bvh();
     println!("Costruzione BVH completata in {:?}", start_bvh.elapsed());
 
+    // Curvatura per vertice, solo se qualche materiale ha una texture di usura degli spigoli
+    if materials.iter().any(|material| material.uses_curvature()) {
+        obj.compute_curvature();
+    }
+
     BaseCamera::center_object(&mut camera, scene_settings.dolly_in as f32, &mut obj);
 
+    // Init Environment -----------------------------------------------
+    let mut scene = Scene::new(obj, lights, materials);
+    let environment_file = "C:\\Users\\renat\\Desktop\\studio.hdr";
+    if scene_settings.sky_enabled {
+        // Cielo analitico con il sole come luce direzionale, al posto della HDRI
+        let sky = SkyModel::new(scene_settings.sun_elevation, scene_settings.sun_azimuth, scene_settings.turbidity);
+        scene.environment = Some(sky.to_environment_map(512, 256, scene_settings.sky_intensity));
+        scene.lights.push(sky.sun_light(scene_settings.sun_intensity));
+    } else if std::path::Path::new(environment_file).exists() {
+        match EnvironmentMap::load(environment_file) {
+            Ok(environment) => scene.environment = Some(environment),
+            Err(e) => println!("Impossibile caricare la mappa d'ambiente: {}", e),
+        }
+    }
+    scene.build_light_tree();
+    // ------------------------------------------------------------------
+
+    (camera, scene, scene_settings)
+}
+
+// Render bloccante dell'intera immagine, usato senza interfaccia (--headless). L'antialiasing
+// adattivo è quello del render a bucket (RenderMode::Buckets); il render progressivo usa un
+// numero fisso di campioni per pixel e si può riprendere da un checkpoint.
+fn setup_scene() -> Vec<u8> {
+    let (width, height) = (SCREEN_WIDTH, SCREEN_HEIGHT);
+    let (camera, scene, scene_settings) = load_scene();
+
     // Renderizza l'immagine    
     let camera = Arc::new(camera);
-    let obj = Arc::new(obj);    
-    let lights_vec: Vec<BaseLight> = lights; // Supponiamo che `lights` sia già un `Vec<BaseLight>`
-    let lights_slice: Box<[BaseLight]> = lights_vec.into_boxed_slice(); // Converti il Vec in Boxed Slice
-    let lights_arc: Arc<[BaseLight]> = Arc::from(lights_slice); // Converti il Boxed Slice in Arc
+    let scene = Arc::new(scene);
     
-    let scene_settings = Arc::new(SceneSettings::new());
-    let num_threads = 31; // Usa tutti i core disponibili
+    let scene_settings = Arc::new(scene_settings);
+    let num_threads = NUM_THREADS; // Usa tutti i core disponibili
     let start_rendering = Instant::now();
 
+    // Ctrl+C ferma il render e salva l'immagine parziale; un secondo Ctrl+C esce subito
+    let cancel = Arc::new(CancelToken::new());
+    let handler_cancel = cancel.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            std::process::exit(130);
+        }
+        println!("Interruzione richiesta: salvo l'immagine parziale (Ctrl+C di nuovo per uscire subito)");
+        handler_cancel.cancel();
+    }) {
+        println!("Impossibile installare il gestore di Ctrl+C: {}", e);
+    }
+
     println!("Iniziando il rendering");
-    // let image_data = render(camera, obj, lights_arc, scene_settings, num_threads);
-    let image_data: Vec<u8> = render_stoacastic(camera, obj, lights_arc, scene_settings, num_threads);
+    // I campioni per pixel variano, e servono per la mappa di calore, solo con l'AA adattivo dei bucket
+    let (image_data, sample_counts) = match scene_settings.render_mode {
+        RenderMode::Buckets => {
+            if resume_requested() {
+                println!("--resume vale solo per il render progressivo: il render a bucket riparte da zero");
+            }
+            let rect = Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+            let buckets = generate_buckets(width, height, rect, scene_settings.bucket_count, scene_settings.bucket_order, scene_settings.seed);
+            let (image_data, sample_counts) = render(camera.clone(), scene.clone(), scene_settings.clone(), buckets, num_threads, &cancel);
+            (image_data, Some(sample_counts))
+        },
+        RenderMode::Progressive => {
+            let (film, _) = render_passes(RenderContext::new(width, height, &camera, &scene, &scene_settings), num_threads, &cancel, resume_requested(), |_, _| {});
+            (film.to_rgba8(), None)
+        },
+    };
     
-    println!("Rendering completato in {:?}", start_rendering.elapsed());
+    if cancel.is_cancelled() {
+        println!("Rendering interrotto dopo {:?}", start_rendering.elapsed());
+    } else {
+        println!("Rendering completato in {:?}", start_rendering.elapsed());
+    }
 
     // Salva l'immagine
     image::save_buffer("output.png", &image_data, SCREEN_WIDTH, SCREEN_HEIGHT, image::ColorType::Rgba8)
     .expect("Impossibile salvare l'immagine");
 
+    if let (true, Some(sample_counts)) = (scene_settings.aa_heatmap, &sample_counts) {
+        let heatmap = aa_heatmap(sample_counts, &scene_settings);
+        image::save_buffer("output_aa_samples.png", &heatmap, SCREEN_WIDTH, SCREEN_HEIGHT, image::ColorType::Rgba8)
+            .expect("Impossibile salvare la mappa dei campioni");
+        let total: u64 = sample_counts.iter().map(|&samples| samples as u64).sum();
+        println!("Campioni AA medi per pixel: {:.2}", total as f64 / sample_counts.len() as f64);
+    }
+
+    if scene_settings.ao_pass && !cancel.is_cancelled() {
+        let ao_data = render_ao_pass(&camera, &scene, &scene_settings);
+        image::save_buffer("output_ao.png", &ao_data, SCREEN_WIDTH, SCREEN_HEIGHT, image::ColorType::Rgba8)
+            .expect("Impossibile salvare la passata AO");
+        println!("Passata AO salvata come 'output_ao.png'");
+    }
+
     println!("Immagine salvata come 'output.png'");
     image_data  
 }
  
 
-const BUCKET_SIZE: u32 = 32; // Puoi regolare questa dimensione
-
 #[macro_use]
 extern crate lazy_static;
 
+lazy_static! {
+    // Materiale usato quando BaseObject::material non punta a un materiale esistente
+    static ref DEFAULT_MATERIAL: BaseMaterial = BaseMaterial::default_material();
+}
+
 
+// Render a bucket: num_threads worker prendono i bucket nell'ordine della lista e ne
+// copiano il contenuto nell'immagine appena finiti. Restituisce l'immagine e i campioni per pixel;
+// se il render viene annullato i bucket non renderizzati restano neri.
 pub fn render(
     camera: Arc<BaseCamera>,
-    obj: Arc<BaseObject>,
-    lights: Arc<[BaseLight]>,
+    scene: Arc<Scene>,
     scene_settings: Arc<SceneSettings>,
-    num_threads: usize
-) -> Vec<u8> {
+    buckets: Vec<Bucket>,
+    num_threads: usize,
+    cancel: &CancelToken
+) -> (Vec<u8>, Vec<u32>) {
     let (width, height) = (SCREEN_WIDTH, SCREEN_HEIGHT);
-    let grid_size = scene_settings.max_samples_aa;
-
-    // Configura il pool di thread
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .build_global()
-        .unwrap();
+    let num_pixels = (width * height) as usize;
+    let image = Mutex::new((vec![0u8; num_pixels * 4], vec![0u32; num_pixels]));
+    let buckets = Mutex::new(buckets);
+    let context = RenderContext::new(width, height, &camera, &scene, &scene_settings);
+    render_buckets(&buckets, context, num_threads, cancel, |bucket, sample_counts| {
+        let mut image = image.lock().unwrap();
+        let (image_data, image_counts) = &mut *image;
+        copy_bucket(bucket, width, image_data);
+        for (i, &samples) in sample_counts.iter().enumerate() {
+            let x = bucket.rect.x + i as u32 % bucket.rect.width;
+            let y = bucket.rect.y + i as u32 / bucket.rect.width;
+            image_counts[(y * width + x) as usize] = samples;
+        }
+    });
+    image.into_inner().unwrap()
+}
 
-    // Calcola il numero di bucket
-    let num_buckets_x = (width + BUCKET_SIZE - 1) / BUCKET_SIZE;
-    let num_buckets_y = (height + BUCKET_SIZE - 1) / BUCKET_SIZE;
-    
-    // Crea una sequenza di bucket dal centro verso l'esterno
-    let center_x = num_buckets_x / 2;
-    let center_y = num_buckets_y / 2;
-    let mut bucket_sequence: Vec<(i32, i32)> = Vec::new();
-    let max_distance = max(center_x, center_y) as i32;
-    
-    for distance in 0..=max_distance {
-        for dy in -distance..=distance {
-            for dx in -distance..=distance {
-                if dx.abs() == distance || dy.abs() == distance {
-                    let bx = center_x as i32 + dx;
-                    let by = center_y as i32 + dy;
-                    if bx >= 0 && by >= 0 && bx < num_buckets_x as i32 && by < num_buckets_y as i32 {
-                        bucket_sequence.push((bx, by));
-                    }
+// Ciclo dei worker sui bucket. Ogni bucket della lista passa da NotProcessed a StartProcessing
+// quando un worker lo prende, a EndProcessing quando il worker ha riempito la sua copia del
+// buffer e a NotVisualized dopo che `deliver` l'ha copiata nell'immagine. I campioni dei pixel
+// a cavallo fra due bucket sono calcolati una volta sola e condivisi con BorderSamples. Prima
+// di ogni bucket i worker passano da `cancel`: in pausa aspettano, dopo l'annullamento non
+// prendono altri bucket e quelli non iniziati restano NotProcessed.
+pub fn render_buckets<F>(
+    buckets: &Mutex<Vec<Bucket>>,
+    context: RenderContext,
+    num_threads: usize,
+    cancel: &CancelToken,
+    deliver: F,
+) where
+    F: Fn(&Bucket, &[u32]) + Sync,
+{
+    let scene_settings = context.scene_settings;
+    let filter = PixelFilter::new(scene_settings.pixel_filter, scene_settings.filter_radius);
+    let rects: Vec<Rect> = buckets.lock().unwrap().iter().map(|bucket| bucket.rect).collect();
+    let border_samples = BorderSamples::new(&rects, context.width, context.height, filter.margin());
+    thread::scope(|s| {
+        for _ in 0..num_threads.max(1) {
+            s.spawn(|_| loop {
+                if !cancel.proceed() {
+                    break;
                 }
-            }
+                let next = buckets.lock().unwrap()
+                    .iter_mut()
+                    .enumerate()
+                    .find(|(_, bucket)| bucket.state == BucketState::NotProcessed)
+                    .map(|(index, bucket)| {
+                        bucket.state = BucketState::StartProcessing;
+                        bucket.buffer = None;  // il worker lavora su una copia
+                        (index, bucket.rect)
+                    });
+                let (index, rect) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                let mut bucket = Bucket::new(rect);
+                let sample_counts = render_bucket(&mut bucket, context, &border_samples);
+                buckets.lock().unwrap()[index].state = BucketState::EndProcessing;
+                deliver(&bucket, &sample_counts);
+                buckets.lock().unwrap()[index].state = BucketState::NotVisualized;
+            });
         }
-    }
+    })
+    .unwrap();
+}
 
-    // Renderizza i bucket in parallelo
-    let pixels: Vec<Rgba<u8>> = bucket_sequence
-        .into_par_iter()
-        .flat_map(|(bx, by)| {
-            let bucket_x = bx as u32 * BUCKET_SIZE;
-            let bucket_y = by as u32 * BUCKET_SIZE;
-            let mut bucket_pixels = Vec::with_capacity((BUCKET_SIZE * BUCKET_SIZE) as usize);
-
-            for y in bucket_y..min(bucket_y + BUCKET_SIZE, height) {
-                for x in bucket_x..min(bucket_x + BUCKET_SIZE, width) {
-                    let mut color = AGColor::new(0.0, 0.0, 0.0);
-                    for sub_x in 0..grid_size {
-                        for sub_y in 0..grid_size {
-                            let u_offset = (sub_x as f32 + 0.5) / grid_size as f32;
-                            let v_offset = (sub_y as f32 + 0.5) / grid_size as f32;
-                            let u = (x as f32 + u_offset) / (width - 1) as f32;
-                            let v = (y as f32 + v_offset) / (height - 1) as f32;
-                            let ray = camera.get_ray(u, v);
-                            color += trace_ray(&ray, &obj, &lights, &scene_settings, 0);
-                        }
-                    }
-                    // Media dei colori dei sottopixel
-                    color /= (grid_size * grid_size) as f32;
-                    bucket_pixels.push(Rgba([
-                        (color.r * 255.0) as u8,
-                        (color.g * 255.0) as u8,
-                        (color.b * 255.0) as u8,
-                        255
-                    ]));
-                }
+// Renderizza un bucket nel suo buffer e restituisce i campioni usati per pixel. Per ricostruire
+// i pixel di bordo servono anche i campioni dei pixel vicini entro il raggio del filtro: li
+// calcola il primo dei bucket confinanti e gli altri li prendono da `border_samples`. Visto che
+// il campionatore è deterministico sono sempre gli stessi, quindi fra un bucket e l'altro non
+// ci sono giunture.
+pub fn render_bucket(bucket: &mut Bucket, context: RenderContext, border_samples: &BorderSamples) -> Vec<u32> {
+    let rect = bucket.rect;
+    let filter = PixelFilter::new(context.scene_settings.pixel_filter, context.scene_settings.filter_radius);
+    let area = rect.with_margin(filter.margin(), context.width, context.height);
+    let (x0, y0) = (area.x, area.y);
+
+    let mut film = Film::new(area.width, area.height, filter);
+    let mut sample_counts = Vec::with_capacity((rect.width * rect.height) as usize);
+    for y in y0..y0 + area.height {
+        for x in x0..x0 + area.width {
+            let (film_samples, samples) = border_samples.get_or_render(x, y, || pixel_samples(x, y, context));
+            for (film_x, film_y, color) in film_samples {
+                film.add_sample(film_x - x0 as f32, film_y - y0 as f32, color);
             }
-            bucket_pixels
-        })
-        .collect();
+            if x >= rect.x && x < rect.x + rect.width && y >= rect.y && y < rect.y + rect.height {
+                sample_counts.push(samples);
+            }
+        }
+    }
 
-    // Converti il vettore di pixel in un vettore di byte raw
-    pixels.into_iter().flat_map(|p| p.0.to_vec()).collect()
+    for y in 0..rect.height {
+        for x in 0..rect.width {
+            let color = film.pixel(rect.x - x0 + x, rect.y - y0 + y);
+            bucket.set_color(x as usize, y as usize, Color::from_rgb(color.r, color.g, color.b));
+        }
+    }
+    sample_counts
 }
 
+// Copia il buffer del bucket nell'immagine RGBA a 8 bit
+pub fn copy_bucket(bucket: &Bucket, width: u32, image_data: &mut [u8]) {
+    for y in 0..bucket.rect.height {
+        for x in 0..bucket.rect.width {
+            if let Some(color) = bucket.get_color(x as usize, y as usize) {
+                let index = (((bucket.rect.y + y) * width + bucket.rect.x + x) * 4) as usize;
+                image_data[index..index + 4].copy_from_slice(&[
+                    (color.r * 255.0) as u8,
+                    (color.g * 255.0) as u8,
+                    (color.b * 255.0) as u8,
+                    255,
+                ]);
+            }
+        }
+    }
+}
 
+// Campioni del pixel (x, y) con l'antialiasing adattivo, ciascuno con la sua posizione continua
+// sulla pellicola, distribuita su tutta l'area del pixel; restituisce anche quanti sono
+fn pixel_samples(x: u32, y: u32, context: RenderContext) -> (Vec<(f32, f32, AGColor)>, u32) {
+    let RenderContext { width, height, camera, scene, scene_settings } = context;
+    let max_samples = scene_settings.max_samples_aa.max(1);
+    let min_samples = scene_settings.min_samples_aa.clamp(1, max_samples);
+    let mut film_samples = Vec::with_capacity(max_samples as usize);
+    // Media e varianza della luminanza con l'algoritmo di Welford
+    let mut mean = 0.0;
+    let mut m2 = 0.0;
+    let mut samples = 0;
+    let mut sampler = Sampler::new(scene_settings.sampler, max_samples, scene_settings.seed);
+
+    while samples < max_samples {
+        sampler.start_pixel_sample(x, y, samples);
+        let (jitter_x, jitter_y) = sampler.get_2d();
+        let film_x = x as f32 + jitter_x;
+        let film_y = y as f32 + jitter_y;
+        let u = film_x / width as f32;
+        let v = film_y / height as f32;
+
+        let ray = camera.get_ray_sampled(u, v, sampler.get_2d());
+        let sample = compute_radiance(&ray, scene, scene_settings, &mut sampler);
+        film_samples.push((film_x, film_y, sample));
+
+        samples += 1;
+        let luminance = sample.luminance();
+        let delta = luminance - mean;
+        mean += delta / samples as f32;
+        m2 += delta * (luminance - mean);
+
+        if samples >= min_samples && samples >= 2 {
+            // Errore standard della media, relativo con un minimo per i pixel scuri
+            let standard_error = (m2 / (samples - 1) as f32 / samples as f32).sqrt();
+            if standard_error <= scene_settings.aa_threshold * mean.max(0.1) {
+                break;
+            }
+        }
+    }
 
+    (film_samples, samples)
+}
 
+// Mappa di calore dei campioni per pixel: blu = min_samples_aa, rosso = max_samples_aa
+fn aa_heatmap(sample_counts: &[u32], scene_settings: &SceneSettings) -> Vec<u8> {
+    let max_samples = scene_settings.max_samples_aa.max(1);
+    let min_samples = scene_settings.min_samples_aa.clamp(1, max_samples);
+    let range = (max_samples - min_samples).max(1) as f32;
+    sample_counts.iter()
+        .flat_map(|&samples| {
+            let t = ((samples.saturating_sub(min_samples)) as f32 / range).clamp(0.0, 1.0);
+            // Blu -> verde -> rosso
+            let r = (2.0 * t - 1.0).max(0.0);
+            let g = 1.0 - (2.0 * t - 1.0).abs();
+            let b = (1.0 - 2.0 * t).max(0.0);
+            [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255]
+        })
+        .collect()
+}
 
-fn render_old_new(
-    camera: Arc<BaseCamera>,
-    obj: Arc<BaseObject>,
-    lights: Arc<[BaseLight]>,
-    scene_settings: Arc<SceneSettings>,
-    num_threads: usize
-) -> Vec<u8> {
-    let (width, height) = (SCREEN_WIDTH, SCREEN_HEIGHT);
-    let grid_size = scene_settings.max_samples_aa;
-    let num_pixels = width * height;
-
-    // Configura il pool di thread globale
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .build_global()
-        .unwrap();
+// Radianza di un campione secondo l'integratore scelto nelle impostazioni
+fn compute_radiance(
+    ray: &BaseRay,
+    scene: &Scene,
+    scene_settings: &SceneSettings,
+    sampler: &mut Sampler,
+) -> AGColor {
+    match scene_settings.integrator {
+        IntegratorType::DirectLighting => trace_ray(ray, scene, scene_settings, 0, sampler),
+        IntegratorType::PathTracing => trace_path(ray, scene, scene_settings, sampler),
+    }
+}
 
-    // Renderizza i pixel in parallelo
-    let pixels: Vec<Rgba<u8>> = (0..num_pixels)
-        .into_par_iter()
-        .map(|i| {
-            let x = i % width;
-            let y = i / width;
+// Funzione per tracciare un raggio e calcolare il colore del pixel
+fn trace_ray(
+    ray: &BaseRay,
+    scene: &Scene,
+    scene_settings: &SceneSettings,
+    depth: u32,
+    sampler: &mut Sampler,
+) -> AGColor {
+    if depth > scene_settings.max_depth {  // Limite di profondità per evitare ricorsione infinita
+        return AGColor::new(0.0, 0.0, 0.0);
+    }
+    let obj = &scene.obj;
+    if let Some((distance, triangle_index)) = obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(ray, obj)) {
+        let mut hit = obj.surface_hit(ray, distance, triangle_index);
+        let material = resolve_material(scene, &mut hit, distance * scene_settings.pixel_spread_angle);
+        let material = material.as_ref();
+        let (hit_point, normal, front_face) = (hit.point, hit.normal, hit.front_face);
+        let view_dir = -ray.direction;
+
+        // Le superfici emissive sono già campionate come luci: qui si aggiunge solo la loro
+        // radianza quando sono viste direttamente o attraverso specchi e vetro
+        let emitted = material.emitted(front_face);
+
+        // Specchi e vetro: un solo raggio ricorsivo, riflesso o rifratto con probabilità data
+        // da Fresnel come nel path tracer, così il numero di raggi non raddoppia a ogni rimbalzo
+        if material.is_specular() {
             let mut color = AGColor::new(0.0, 0.0, 0.0);
-
-            for sub_x in 0..grid_size {
-                for sub_y in 0..grid_size {
-                    let u_offset = (sub_x as f32 + 0.5) / grid_size as f32;
-                    let v_offset = (sub_y as f32 + 0.5) / grid_size as f32;
-                    let u = (x as f32 + u_offset) / (width - 1) as f32;
-                    let v = (y as f32 + v_offset) / (height - 1) as f32;
-                    let ray = camera.get_ray(u, v);
-                    color += trace_ray(&ray, &obj, &lights, &scene_settings, 0);
-                }
+            if let Some((dir, weight)) = material.sample_specular(&normal, &view_dir, front_face, sampler.get_3d()) {
+                let secondary = BaseRay::new(offset_ray_origin(hit_point, normal, dir), dir);
+                let traced = trace_ray(&secondary, scene, scene_settings, depth + 1, sampler);
+                color = multiply_colors(&traced, &weight);
             }
+            // Assorbimento di Beer-Lambert lungo il tratto percorso dentro il materiale
+            if material.material_type == MaterialType::Glass && !front_face {
+                color = multiply_colors(&color, &material.transmittance(distance));
+            }
+            return add_colors(&color, &emitted);
+        }
 
-            // Media dei colori dei sottopixel
-            color /= (grid_size * grid_size) as f32;
-            Rgba([
-                (color.b * 255.0) as u8,
-                (color.g * 255.0) as u8,
-                (color.r * 255.0) as u8,
-                255
-            ])
-        })
-        .collect();
-
-    // Converti il vettore di pixel in un vettore di byte raw
-    pixels.into_iter().flat_map(|p| p.0.to_vec()).collect()
-}
+        let mut color = direct_lighting(hit_point, normal, view_dir, material, scene, scene_settings, sampler);
 
-fn render_old(
-        camera: Arc<BaseCamera>,
-        obj: Arc<BaseObject>,
-        lights: Arc<[BaseLight]>,
-        scene_settings: Arc<SceneSettings>,
-        num_threads: usize
-    ) -> Vec<u8> {
-    let (width, height) = (SCREEN_WIDTH, SCREEN_HEIGHT);
-    let num_pixels = width * height;
-    let grid_size = scene_settings.max_samples_aa;// scene_settings.grid_size;  // Assumi che questa impostazione esista e sia, per esempio, 2 per 2x2, 3 per 3x3, ecc.
-
-    // Configura il numero di thread
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .build_global()
-        .unwrap();
-        
-    // Crea un vettore di pixel in parallelo
-    // Invece di mappare direttamente i pixel, li inverto nel processo di conversione
-    // Crea un vettore di pixel in parallelo
-    let pixels: Vec<Rgba<u8>> = (0..num_pixels)
-    .into_par_iter()
-    .map(|i| {
-        let x = i % width;
-        let y = i / width;
-        let mut color = AGColor::new(0.0, 0.0, 0.0);
-
-        for sub_x in 0..grid_size {
-            for sub_y in 0..grid_size {
-                let u_offset = (sub_x as f32 + 0.5) / grid_size as f32;
-                let v_offset = (sub_y as f32 + 0.5) / grid_size as f32;
-
-                let u = (x as f32 + u_offset) / (width - 1) as f32;
-                let v = (y as f32 + v_offset) / (height - 1) as f32;
-
-                let ray = camera.get_ray(u, v);
-                color += trace_ray(&ray, &obj, &lights, &scene_settings, 0);
-            }
+        // Ambient Occlusion
+        if scene_settings.ao_enabled {
+            let ao = compute_ao(hit_point, normal, obj, scene_settings, sampler);
+            color = multiply_color_scalar(&color, ao as f32);
         }
 
-        // Media dei colori dei sottopixel
-        color /= (grid_size * grid_size) as f32;
+        // Applicazione dei moltiplicatori
+        color = multiply_color_scalar(&color, 1.0 - (scene_settings.shadow_mult as f32 / 100.0));
+        color = multiply_color_scalar(&color, 1.0 - (scene_settings.ao_mult as f32 / 100.0));
+        add_colors(&color, &emitted)
+    } else if depth > 0 || scene_settings.env_visible {
+        background(&ray.direction, scene, scene_settings)  // Nessuna intersezione, colore di sfondo
+    } else {
+        AGColor::new(0.0, 0.0, 0.0)
+    }
+}
 
-        Rgba([
-            (color.b * 255.0) as u8,
-            (color.g * 255.0) as u8,
-            (color.r * 255.0) as u8,
-            255
-        ])
-    })
-    .collect();
-        // Converti il vettore di pixel in un vettore di byte raw
-        pixels.into_iter().flat_map(|p| p.0.to_vec()).collect()
+// Radianza dell'ambiente nella direzione data (nero se la scena non ha una mappa d'ambiente)
+pub fn background(direction: &Vector3, scene: &Scene, scene_settings: &SceneSettings) -> AGColor {
+    match &scene.environment {
+        Some(environment) => environment.lookup(direction, scene_settings.env_rotation.to_radians()) * scene_settings.env_intensity,
+        None => AGColor::new(0.0, 0.0, 0.0),
     }
+}
 
+// Materiale nel punto colpito con le texture applicate; la normal map aggiorna hit.normal.
+// `footprint` è la larghezza del cono del raggio nel punto, usata per scegliere la mip-map.
+fn resolve_material<'a>(scene: &'a Scene, hit: &mut SurfaceHit, footprint: f32) -> Cow<'a, BaseMaterial> {
+    let obj = &scene.obj;
+    let material = scene.materials.get(obj.material_of(hit.triangle_index) as usize).unwrap_or(&DEFAULT_MATERIAL);
+    if !material.has_textures() && material.normal_texture.is_none() {
+        return Cow::Borrowed(material);
+    }
+    let ctx = obj.texture_context(hit, footprint);
+    hit.normal = material.shading_normal(&ctx, &hit.normal);
+    material.at_hit(&ctx)
+}
 
+// True se un raggio d'ombra da `origin` lungo `direction` incontra la geometria prima di `max_distance`
+fn is_occluded(obj: &BaseObject, origin: Vector3, direction: Vector3, max_distance: f32) -> bool {
+    let shadow_ray = BaseRay::new(origin, direction);
+    match obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(&shadow_ray, obj)) {
+        Some((shadow_dist, _)) => shadow_dist < max_distance,
+        None => false,
+    }
+}
 
-fn render_stoacastic(
-    camera: Arc<BaseCamera>,
-    obj: Arc<BaseObject>,
-    lights: Arc<[BaseLight]>,
-    scene_settings: Arc<SceneSettings>,
-    num_threads: usize
-) -> Vec<u8> {
-    let (width, height) = (SCREEN_WIDTH, SCREEN_HEIGHT);
-    let num_pixels = width * height;
-    let samples_aa = scene_settings.max_samples_aa;
+// Punto da illuminare: posizione, normale di shading rivolta verso l'osservatore,
+// direzione verso l'osservatore e materiale nel punto
+#[derive(Clone, Copy)]
+pub struct ShadingPoint<'a> {
+    pub hit_point: Vector3,
+    pub normal: Vector3,
+    pub view_dir: Vector3,
+    pub material: &'a BaseMaterial,
+}
 
-    // Configura il numero di thread
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(num_threads)
-        .build_global()
-        .unwrap();
+// Luce diretta in un punto: somma dei contributi di tutte le luci, con i raggi d'ombra.
+// Usata sia dal rendering a luce diretta sia come next-event estimation dal path tracer.
+pub fn direct_lighting(
+    hit_point: Vector3,
+    normal: Vector3,
+    view_dir: Vector3,
+    material: &BaseMaterial,
+    scene: &Scene,
+    scene_settings: &SceneSettings,
+    sampler: &mut Sampler,
+) -> AGColor {
+    let obj = &scene.obj;
+    let point = ShadingPoint { hit_point, normal, view_dir, material };
+    let mut color = AGColor::new(0.0, 0.0, 0.0);
+    let samples = scene_settings.max_samples_light.max(1);
+
+    match scene.active_light_tree(scene_settings.light_sampling) {
+        Some(light_tree) => {
+            // `samples` scelte in tutto, distribuite fra le luci dall'albero. Ogni luce scelta
+            // c volte viene valutata con c campioni e pesata per c / (samples * pmf), che in
+            // media restituisce la somma di tutte le luci.
+            let mut picks: Vec<(LightRef, u32, f32)> = Vec::new();
+            for _ in 0..samples {
+                if let Some((light_ref, pmf)) = light_tree.sample(&hit_point, &scene.lights, sampler.get_1d()) {
+                    match picks.iter_mut().find(|(picked, _, _)| *picked == light_ref) {
+                        Some((_, count, _)) => *count += 1,
+                        None => picks.push((light_ref, 1, pmf)),
+                    }
+                }
+            }
+            for (light_ref, count, pmf) in picks {
+                // Per il MIS la strategia delle luci dedica in media samples * pmf campioni a questa luce
+                let light_count = samples as f32 * pmf;
+                let contribution = match light_ref {
+                    LightRef::Light(index) => light_contribution(&scene.lights[index], count, light_count, &point, scene, scene_settings, sampler),
+                    LightRef::Mesh(index) => mesh_light_contribution(&scene.mesh_lights[index], count, light_count, &point, scene, scene_settings, sampler),
+                };
+                color = add_colors(&color, &(contribution * (count as f32 / (samples as f32 * pmf))));
+            }
+            // Le luci all'infinito non sono nell'albero
+            for light in scene.lights.iter().filter(|light| light.is_infinite()) {
+                color = add_colors(&color, &light_contribution(light, samples, samples as f32, &point, scene, scene_settings, sampler));
+            }
+        },
+        None => {
+            let (strata_x, strata_y) = light_strata(samples);
+            for light in &scene.lights {
+                color = add_colors(&color, &light_contribution(light, samples, (strata_x * strata_y) as f32, &point, scene, scene_settings, sampler));
+            }
+            for mesh_light in &scene.mesh_lights {
+                color = add_colors(&color, &mesh_light_contribution(mesh_light, samples, samples as f32, &point, scene, scene_settings, sampler));
+            }
+        },
+    }
 
-    // Crea un vettore di pixel in parallelo
-    let pixels: Vec<Rgba<u8>> = (0..num_pixels)
-        .into_par_iter()
-        .map(|i| {
-            let x = i % width;
-            let y = i / width;
+    // Luce dell'ambiente, campionata in proporzione alla luminanza della mappa
+    if let Some(environment) = &scene.environment {
+        let samples = scene_settings.max_samples_env.max(1);
+        let rotation = scene_settings.env_rotation.to_radians();
+        let mut env_color = AGColor::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let (u1, u2) = sampler.get_2d();
+            let (light_dir, radiance, pdf) = match environment.sample(u1, u2, rotation) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let n_dot_l = normal.dot(&light_dir);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            if scene_settings.shadows_enabled && is_occluded(obj, hit_point + normal * 0.001, light_dir, f32::INFINITY) {
+                continue;
+            }
+            let brdf = material.eval(&normal, &view_dir, &light_dir);
+            let mis = power_heuristic(samples as f32, pdf, bsdf_sample_count(scene_settings), material.pdf(&normal, &view_dir, &light_dir));
+            env_color += brdf * radiance * (n_dot_l * mis / pdf);
+        }
+        color = add_colors(&color, &(env_color * (scene_settings.env_intensity / samples as f32)));
+    }
 
-            let mut color = AGColor::new(0.0, 0.0, 0.0);
+    // Seconda strategia del MIS: direzioni campionate dalla BRDF che colpiscono le luci
+    add_colors(&color, &bsdf_light_contribution(hit_point, normal, view_dir, material, scene, scene_settings, sampler))
+}
 
-            for _ in 0..samples_aa {
-                let random_offset_x = rand::random::<f32>() / (width as f32);
-                let random_offset_y = rand::random::<f32>() / (height as f32);
-                let u = (x as f32 + random_offset_x) / (width - 1) as f32;
-                let v = (y as f32 + random_offset_y) / (height - 1) as f32;
+// Luce raccolta dalle direzioni campionate con la BRDF, che colpiscono luci ad area, oggetti
+// emissivi o l'ambiente. Ogni contributo è pesato con l'euristica della potenza contro il
+// campionamento delle luci, che copre le stesse direzioni: le luci grandi sui materiali
+// lucidi, dove il campionamento della luce è rumoroso, vengono prese soprattutto da qui.
+fn bsdf_light_contribution(
+    hit_point: Vector3,
+    normal: Vector3,
+    view_dir: Vector3,
+    material: &BaseMaterial,
+    scene: &Scene,
+    scene_settings: &SceneSettings,
+    sampler: &mut Sampler,
+) -> AGColor {
+    let obj = &scene.obj;
+    let mut color = AGColor::new(0.0, 0.0, 0.0);
+    if !scene_settings.mis_enabled || scene_settings.max_samples_bsdf == 0 || !scene.has_bsdf_emitters() {
+        return color;
+    }
+    let samples = scene_settings.max_samples_bsdf;
+    let origin = hit_point + normal * 0.001;
 
-                let ray = camera.get_ray(u, v);
-                color += trace_ray(&ray, &obj, &lights, &scene_settings, 0);
+    for _ in 0..samples {
+        let (light_dir, weight, pdf) = match material.sample(&normal, &view_dir, sampler.get_3d()) {
+            Some(sample) if sample.2 > 0.0 => sample,
+            _ => continue,
+        };
+        // Un solo raggio per tutte le sorgenti: la geometria colpita fa ombra alle luci più lontane
+        let ray = BaseRay::new(origin, light_dir);
+        let geometry_hit = obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(&ray, obj));
+        let blocker_distance = match geometry_hit {
+            Some((distance, _)) if scene_settings.shadows_enabled => distance,
+            _ => f32::INFINITY,
+        };
+
+        for (index, light) in scene.lights.iter().enumerate() {
+            if !matches!(light.light_type, LightType::Area) {
+                continue;
+            }
+            let light_hit = match light.intersect_area(&hit_point, &light_dir) {
+                Some(light_hit) if light_hit.distance < blocker_distance || !light.casts_shadows => light_hit,
+                _ => continue,
+            };
+            let solid_angle = light.solid_angle(&hit_point);
+            let attenuation = light.attenuation(light_hit.distance);
+            if solid_angle <= 0.0 || attenuation <= 0.0 {
+                continue;
             }
+            let light_pdf = 1.0 / light_hit.weight;
+            let mis = power_heuristic(samples as f32, pdf, light_sample_count(LightRef::Light(index), &hit_point, scene, scene_settings), light_pdf);
+            let radiance = light.color * (light.intensity * PI * attenuation / solid_angle);
+            color += weight * radiance * mis;
+        }
 
-            color /= samples_aa as f32; // Media dei colori ottenuti
+        // Il triangolo colpito appartiene al più a una luce a mesh
+        if let Some((distance, triangle_index)) = geometry_hit {
+            for (index, mesh_light) in scene.mesh_lights.iter().enumerate() {
+                let light_weight = mesh_light.weight(obj, triangle_index, &light_dir, distance);
+                if light_weight > 0.0 {
+                    let mis = power_heuristic(samples as f32, pdf, light_sample_count(LightRef::Mesh(index), &hit_point, scene, scene_settings), 1.0 / light_weight);
+                    color += weight * mesh_light.emission * mis;
+                    break;
+                }
+            }
+        }
+        // Senza ombre l'ambiente è visibile anche oltre la geometria, come nella prima strategia
+        if geometry_hit.is_none() || !scene_settings.shadows_enabled {
+            if let Some(environment) = &scene.environment {
+                let env_pdf = environment.pdf(&light_dir, scene_settings.env_rotation.to_radians());
+                let mis = power_heuristic(samples as f32, pdf, scene_settings.max_samples_env.max(1) as f32, env_pdf);
+                color += weight * background(&light_dir, scene, scene_settings) * mis;
+            }
+        }
+    }
+    color * (1.0 / samples as f32)
+}
 
-            Rgba([
-                (color.r * 255.0) as u8,
-                (color.g * 255.0) as u8,
-                (color.b * 255.0) as u8,
-                255
-            ])
-        })
-        .collect();
+// Euristica della potenza (beta = 2) di Veach per la strategia f con n_f campioni
+fn power_heuristic(n_f: f32, pdf_f: f32, n_g: f32, pdf_g: f32) -> f32 {
+    let f = n_f * pdf_f;
+    let g = n_g * pdf_g;
+    if f <= 0.0 {
+        return 0.0;
+    }
+    (f * f) / (f * f + g * g)
+}
 
-    // Converti il vettore di pixel in un vettore di byte raw
-    pixels.into_iter().flat_map(|p| p.0.to_vec()).collect()
+// Campioni della BRDF che la seconda strategia del MIS usa per punto (zero senza MIS)
+fn bsdf_sample_count(scene_settings: &SceneSettings) -> f32 {
+    if scene_settings.mis_enabled { scene_settings.max_samples_bsdf as f32 } else { 0.0 }
 }
 
-// Funzione per tracciare un raggio e calcolare il colore del pixel
-fn trace_ray(
-    ray: &BaseRay,
-    obj: &BaseObject,
-    lights: &[BaseLight],
+// Campioni (attesi) che la strategia delle luci dedica alla sorgente nel punto
+fn light_sample_count(light: LightRef, hit_point: &Vector3, scene: &Scene, scene_settings: &SceneSettings) -> f32 {
+    let samples = scene_settings.max_samples_light.max(1);
+    match scene.active_light_tree(scene_settings.light_sampling) {
+        Some(light_tree) => samples as f32 * light_tree.pmf(hit_point, &scene.lights, light),
+        None => match light {
+            LightRef::Light(_) => {
+                let (strata_x, strata_y) = light_strata(samples);
+                (strata_x * strata_y) as f32
+            },
+            LightRef::Mesh(_) => samples as f32,
+        },
+    }
+}
+
+// Contributo di una singola luce stimato con `samples` campioni (le luci puntiformi ne usano uno).
+// `light_count` è il numero di campioni con cui la strategia delle luci copre questa luce,
+// per i pesi del MIS con il campionamento della BRDF.
+fn light_contribution(
+    light: &BaseLight,
+    samples: u32,
+    light_count: f32,
+    point: &ShadingPoint,
+    scene: &Scene,
     scene_settings: &SceneSettings,
-    depth: u32,
+    sampler: &mut Sampler,
 ) -> AGColor {
-    if depth > 5 {  // Limite di profondità per evitare ricorsione infinita
-        return AGColor::new(0.0, 0.0, 0.0);
-    }
-    if let Some((distance, triangle_index)) = obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(ray, obj)) {
-        let hit_point = ray.origin + ray.direction * distance;
-        let normal = obj.norm[triangle_index];
-        let mut color = AGColor::new(0.0, 0.0, 0.0);
-        let diffuse_color = AGColor::new(0.75, 0.75, 0.75);
-
-        for light in lights {
-            match light.light_type {
-                LightType::Area => {
-                    let (width, height) = light.area_size;
-                    let samples = scene_settings.max_samples_light;
-                    let mut area_color = AGColor::new(0.0, 0.0, 0.0);
-
-                    for _ in 0..samples {
-                        let random_x = random::<f32>() - 0.5;
-                        let random_y = random::<f32>() - 0.5;
-                        let sample_pos = light.position + 
-                            light.direction.cross(&Vector3::new(0.0, 1.0, 0.0)).normalize() * ((random_x * width) as f32) +
-                            light.direction.cross(&Vector3::new(1.0, 0.0, 0.0)).normalize() * ((random_y * height) as f32);
-                        
-                        let light_dir = (sample_pos - hit_point).normalize();
-                        let light_distance = (sample_pos - hit_point).length();
-
-                        if scene_settings.shadows_enabled {
-                            let shadow_ray = BaseRay::new(hit_point + normal * 0.001, light_dir);
-                            if let Some((shadow_dist, _)) = obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(&shadow_ray, obj)) {
-                                if shadow_dist < light_distance {
-                                    continue;  // Punto in ombra
-                                }
-                            }
-                        }
-
-                        let diff = normal.dot(&light_dir).max(0.0) as f32;
-                        area_color = add_colors(&area_color, &multiply_color_scalar(&multiply_colors(&diffuse_color, &light.color), diff * light.intensity));
-
-                        let reflect_dir = reflect(-light_dir, normal);
-                        let spec = ray.direction.dot(&reflect_dir).max(0.0).powf(32.0) as f32;
-                        area_color = add_colors(&area_color, &multiply_color_scalar(&light.color, spec * light.intensity * 0.5));
-                    }
-                    color = add_colors(&color, &multiply_color_scalar(&area_color, 1.0 / samples as f32));
-                },
-                _ => {
-                    let light_dir = (light.position - hit_point).normalize();
-                    let light_distance = (light.position - hit_point).length();
-
-                    if scene_settings.shadows_enabled {
-                        let shadow_ray = BaseRay::new(hit_point + normal * 0.001, light_dir);
-                        if let Some((shadow_dist, _)) = obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(&shadow_ray, obj)) {
-                            if shadow_dist < light_distance {
-                                continue;  // Punto in ombra
-                            }
-                        }
+    let ShadingPoint { hit_point, normal, view_dir, material } = *point;
+    let obj = &scene.obj;
+    let shadows = scene_settings.shadows_enabled && light.casts_shadows;
+    match light.light_type {
+        LightType::Area => {
+            // Campionamento stratificato della superficie della luce. Ogni campione pesa per
+            // il suo angolo solido (coseno di emissione / distanza² / pdf); la somma è poi
+            // normalizzata per l'angolo solido totale, così l'intensità resta quella della
+            // luce come per i punti luce senza falloff, ma le ombre morbide e la forma
+            // della luce sono corrette.
+            let solid_angle = light.solid_angle(&hit_point);
+            if solid_angle <= 0.0 {
+                return AGColor::new(0.0, 0.0, 0.0);
+            }
+            let (strata_x, strata_y) = light_strata(samples);
+            let mut area_color = AGColor::new(0.0, 0.0, 0.0);
+
+            for sy in 0..strata_y {
+                for sx in 0..strata_x {
+                    let (j1, j2) = sampler.get_2d();
+                    let u1 = (sx as f32 + j1) / strata_x as f32;
+                    let u2 = (sy as f32 + j2) / strata_y as f32;
+                    let sample = match light.sample_area(&hit_point, u1, u2) {
+                        Some(sample) => sample,
+                        None => continue,
+                    };
+
+                    if shadows && is_occluded(obj, hit_point + normal * 0.001, sample.direction, sample.distance) {
+                        continue;  // Punto in ombra
                     }
 
-                    let diff = normal.dot(&light_dir).max(0.0) as f32;
-                    color = add_colors(&color, &multiply_color_scalar(&multiply_colors(&diffuse_color, &light.color), diff * light.intensity));
-
-                    let reflect_dir = reflect(-light_dir, normal);
-                    let spec = ray.direction.dot(&reflect_dir).max(0.0).powf(32.0) as f32;
-                    color = add_colors(&color, &multiply_color_scalar(&light.color, spec * light.intensity * 0.5));
+                    let attenuation = light.attenuation(sample.distance);
+                    let mis = power_heuristic(light_count, 1.0 / sample.weight, bsdf_sample_count(scene_settings), material.pdf(&normal, &view_dir, &sample.direction));
+                    area_color = add_colors(&area_color, &multiply_color_scalar(&shade_light(material, &normal, &view_dir, &sample.direction, light), sample.weight * attenuation * mis));
                 }
             }
+            multiply_color_scalar(&area_color, 1.0 / ((strata_x * strata_y) as f32 * solid_angle))
+        },
+        LightType::Directional => {
+            // Luce all'infinito: usa solo light.direction, la posizione è ignorata.
+            // Con un raggio angolare servono più campioni per le ombre morbide.
+            let samples = if light.angular_radius > 0.0 { samples.max(1) } else { 1 };
+            let mut sun_color = AGColor::new(0.0, 0.0, 0.0);
+
+            for _ in 0..samples {
+                let (u1, u2) = sampler.get_2d();
+                let light_dir = light.sample_directional(u1, u2);
+
+                if shadows && is_occluded(obj, hit_point + normal * 0.001, light_dir, f32::INFINITY) {
+                    continue;  // Punto in ombra
+                }
 
-            // Applica il falloff della luce
-            match light.falloff {
-                FalloffType::Linear => {
-                    let light_distance = (light.position - hit_point).length();
-                    let attenuation = 1.0 / light_distance as f32;
-                    color = multiply_color_scalar(&color, attenuation);
-                },
-                FalloffType::Quadratic => {
-                    let light_distance = (light.position - hit_point).length();
-                    let attenuation = 1.0 / (light_distance * light_distance);
-                    color = multiply_color_scalar(&color, attenuation as f32);
-                },
-                FalloffType::None => {}
+                sun_color = add_colors(&sun_color, &shade_light(material, &normal, &view_dir, &light_dir, light));
+            }
+            multiply_color_scalar(&sun_color, 1.0 / samples as f32)
+        },
+        _ => {
+            let light_dir = (light.position - hit_point).normalize();
+            let light_distance = (light.position - hit_point).length();
+
+            // Gli spot illuminano solo dentro il loro cono, orientato lungo light.direction
+            let cone = match light.light_type {
+                LightType::Spot => light.spot_attenuation(&-light_dir),
+                _ => 1.0,
+            };
+            let attenuation = cone * light.attenuation(light_distance);
+            if attenuation <= 0.0 {
+                return AGColor::new(0.0, 0.0, 0.0);
             }
+
+            if shadows && is_occluded(obj, hit_point + normal * 0.001, light_dir, light_distance) {
+                return AGColor::new(0.0, 0.0, 0.0);  // Punto in ombra
+            }
+
+            multiply_color_scalar(&shade_light(material, &normal, &view_dir, &light_dir, light), attenuation)
         }
+    }
+}
 
-        // Ambient Occlusion
-        if scene_settings.ao_enabled {
-            let ao = compute_ao(hit_point, normal, obj, scene_settings.max_samples_ao);
-            color = multiply_color_scalar(&color, ao as f32);
+// Oggetti emissivi: triangolo scelto in proporzione all'area, punto uniforme sul triangolo.
+// Qui l'emissione è una radianza fisica, quindi niente normalizzazione come per le luci Area.
+fn mesh_light_contribution(
+    mesh_light: &MeshLight,
+    samples: u32,
+    light_count: f32,
+    point: &ShadingPoint,
+    scene: &Scene,
+    scene_settings: &SceneSettings,
+    sampler: &mut Sampler,
+) -> AGColor {
+    let ShadingPoint { hit_point, normal, view_dir, material } = *point;
+    let obj = &scene.obj;
+    let samples = samples.max(1);
+    let mut mesh_color = AGColor::new(0.0, 0.0, 0.0);
+    for _ in 0..samples {
+        let (u0, u1, u2) = sampler.get_3d();
+        let sample = match mesh_light.sample(obj, &hit_point, u0, u1, u2) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let n_dot_l = normal.dot(&sample.direction);
+        if n_dot_l <= 0.0 {
+            continue;
         }
+        // Il raggio d'ombra si ferma poco prima della superficie emissiva stessa
+        if scene_settings.shadows_enabled && is_occluded(obj, hit_point + normal * 0.001, sample.direction, sample.distance * 0.999 - 0.001) {
+            continue;
+        }
+        let brdf = material.eval(&normal, &view_dir, &sample.direction);
+        let mis = power_heuristic(light_count, 1.0 / sample.weight, bsdf_sample_count(scene_settings), material.pdf(&normal, &view_dir, &sample.direction));
+        mesh_color += brdf * mesh_light.emission * (n_dot_l * sample.weight * mis);
+    }
+    mesh_color * (1.0 / samples as f32)
+}
 
-        // Applicazione dei moltiplicatori
-        color = multiply_color_scalar(&color, 1.0 - (scene_settings.shadow_mult as f32 / 100.0));
-        color = multiply_color_scalar(&color, 1.0 - (scene_settings.ao_mult as f32 / 100.0));
-        color
-    } else {
-        AGColor::new(0.0, 0.0, 0.0)  // Nessuna intersezione, colore di sfondo
+// Griglia di strati (x, y) con almeno `samples` celle, il più quadrata possibile
+fn light_strata(samples: u32) -> (u32, u32) {
+    let samples = samples.max(1);
+    let strata_x = (samples as f32).sqrt().ceil() as u32;
+    let strata_y = (samples + strata_x - 1) / strata_x;
+    (strata_x, strata_y)
+}
+
+// Contributo di una luce con la BRDF GGX: f(v, l) * E * cos(theta_l).
+// L'intensità è in unità di PI (vedi BaseLight::intensity): moltiplicata per PI compensa il
+// 1 / PI della parte lambertiana, così la luminosità diffusa resta quella dello shading
+// lambertiano precedente, in cui una luce di intensità 1 rendeva bianca una superficie bianca.
+fn shade_light(material: &BaseMaterial, normal: &Vector3, view_dir: &Vector3, light_dir: &Vector3, light: &BaseLight) -> AGColor {
+    let n_dot_l = normal.dot(light_dir);
+    if n_dot_l <= 0.0 {
+        return AGColor::new(0.0, 0.0, 0.0);
     }
+    let brdf = material.eval(normal, view_dir, light_dir);
+    multiply_color_scalar(&multiply_colors(&brdf, &light.color), n_dot_l * light.intensity * PI)
 }
 
 // Funzioni helper per le operazioni sui colori
@@ -668,27 +1127,85 @@ fn multiply_color_scalar(c: &AGColor, s: f32) -> AGColor {
     AGColor::new(c.r * s, c.g * s, c.b * s)
 }
 
-fn reflect(v: Vector3, n: Vector3) -> Vector3 {
-    v - n * 2.0 * v.dot(&n)
+// Sposta l'origine di un raggio secondario dal lato della superficie verso cui è diretto
+fn offset_ray_origin(point: Vector3, normal: Vector3, direction: Vector3) -> Vector3 {
+    if direction.dot(&normal) >= 0.0 {
+        point + normal * 0.001
+    } else {
+        point - normal * 0.001
+    }
 }
 
-fn compute_ao(point: Vector3, normal: Vector3, obj: &BaseObject, samples: u32) -> f32 {
+fn compute_ao(point: Vector3, normal: Vector3, obj: &BaseObject, scene_settings: &SceneSettings, sampler: &mut Sampler) -> f32 {
+    let samples = scene_settings.max_samples_ao.max(1);
+    let radius = ao_radius(obj, scene_settings);
     let mut occlusion = 0.0;
     for _ in 0..samples {
-        let sample_vec = Vector3::random_in_hemisphere(&normal);
+        // Campionamento proporzionale al coseno: la media dei campioni è già l'occlusione
+        // pesata per il coseno, senza dividere per la pdf
+        let (u1, u2) = sampler.get_2d();
+        let sample_vec = normal.from_local(&Vector3::cosine_sample_hemisphere(u1, u2));
         let ray = BaseRay::new(point + normal * 0.001, sample_vec);
         if let Some((distance, _)) = obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(&ray, obj)) {
-            if distance < 1.0 {
-                occlusion += 1.0 - distance;
+            if distance < radius {
+                let t = distance / radius;
+                occlusion += match scene_settings.ao_falloff {
+                    AoFalloff::None => 1.0,
+                    AoFalloff::Linear => 1.0 - t,
+                    AoFalloff::Quadratic => (1.0 - t) * (1.0 - t),
+                    AoFalloff::Exponential => (-4.0 * t).exp(),
+                };
             }
         }
     }
     1.0 - (occlusion / samples as f32)
 }
 
+// Raggio dell'AO in unità di scena: se non impostato segue la dimensione dell'oggetto,
+// così funziona sia con gli STL in millimetri sia con le scene in metri
+fn ao_radius(obj: &BaseObject, scene_settings: &SceneSettings) -> f32 {
+    if scene_settings.ao_radius > 0.0 {
+        return scene_settings.ao_radius;
+    }
+    match &obj.boundingbox {
+        Some(bbox) => (bbox.diagonal() * 0.1).max(1e-4),
+        None => 1.0,
+    }
+}
+
+// Passata di sola AO in scala di grigi, con un campione al centro di ogni pixel
+fn render_ao_pass(camera: &BaseCamera, scene: &Scene, scene_settings: &SceneSettings) -> Vec<u8> {
+    let (width, height) = (SCREEN_WIDTH, SCREEN_HEIGHT);
+    let obj = &scene.obj;
+    (0..width * height)
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            let x = i % width;
+            let y = i / width;
+            let u = (x as f32 + 0.5) / (width - 1) as f32;
+            let v = (y as f32 + 0.5) / (height - 1) as f32;
+            let mut sampler = Sampler::new(scene_settings.sampler, 1, scene_settings.seed);
+            sampler.start_pixel_sample(x, y, 0);
+            let ray = camera.get_ray_sampled(u, v, sampler.get_2d());
+            let ao = match obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(&ray, obj)) {
+                Some((distance, triangle_index)) => {
+                    let hit = obj.surface_hit(&ray, distance, triangle_index);
+                    compute_ao(hit.point, hit.normal, obj, scene_settings, &mut sampler)
+                },
+                None => 1.0,
+            };
+            let value = (ao.clamp(0.0, 1.0) * 255.0) as u8;
+            [value, value, value, 255]
+        })
+        .collect()
+}
+
 use std::cmp::{max, min};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
 
-pub fn generate_buckets(width: u32, height: u32, rect: Rect, num_buckets: u32) -> Vec<Bucket> {
+pub fn generate_buckets(width: u32, height: u32, rect: Rect, num_buckets: u32, order: BucketOrder, seed: u32) -> Vec<Bucket> {
     let mut buckets = Vec::new();
     let min_buckets = max(num_cpus::get() as u32 * 3, 16);
     let (num_buckets_x, num_buckets_y) = if rect.width != width {
@@ -713,13 +1230,14 @@ pub fn generate_buckets(width: u32, height: u32, rect: Rect, num_buckets: u32) -
         for x in 0..num_buckets_x {
             let bucket_x = rect.x + x * bucket_width;
             let bucket_y = rect.y + y * bucket_height;
+            // L'ultima colonna e l'ultima riga arrivano fino al bordo del rettangolo
             let current_bucket_width = if x == num_buckets_x - 1 {
-                min(bucket_width, rect.x + rect.width - bucket_x)
+                rect.x + rect.width - bucket_x
             } else {
                 bucket_width
             };
             let current_bucket_height = if y == num_buckets_y - 1 {
-                min(bucket_height, rect.y + rect.height - bucket_y)
+                rect.y + rect.height - bucket_y
             } else {
                 bucket_height
             };
@@ -736,12 +1254,191 @@ pub fn generate_buckets(width: u32, height: u32, rect: Rect, num_buckets: u32) -
         }
     }
 
-    sort_buckets(&mut buckets, center_x, center_y, bucket_width, bucket_height, num_buckets);
+    sort_buckets(&mut buckets, order, seed, center_x, center_y, bucket_width, bucket_height);
     buckets
 }
 
-// Assumiamo che questa funzione sia implementata altrove
-fn sort_buckets(buckets: &mut Vec<Bucket>, center_x: u32, center_y: u32, bucket_width: u32, bucket_height: u32, num_buckets: u32) {
-    // Implementazione della funzione di ordinamento
+// Ordina i bucket secondo `order`; (center_x, center_y) è il centro del rettangolo in pixel.
+// `seed` fissa l'ordine casuale, così a parità di seme anche l'ordine dei bucket si ripete.
+fn sort_buckets(buckets: &mut Vec<Bucket>, order: BucketOrder, seed: u32, center_x: u32, center_y: u32, bucket_width: u32, bucket_height: u32) {
+    let origin_x = buckets.iter().map(|bucket| bucket.rect.x).min().unwrap_or(0);
+    let origin_y = buckets.iter().map(|bucket| bucket.rect.y).min().unwrap_or(0);
+    // Posizione del bucket nella griglia e distanza del suo centro dal centro, in bucket
+    let cell = |bucket: &Bucket| ((bucket.rect.x - origin_x) / bucket_width, (bucket.rect.y - origin_y) / bucket_height);
+    let offset = |bucket: &Bucket| {
+        let x = bucket.rect.x as f32 + bucket.rect.width as f32 * 0.5 - center_x as f32;
+        let y = bucket.rect.y as f32 + bucket.rect.height as f32 * 0.5 - center_y as f32;
+        (x / bucket_width as f32, y / bucket_height as f32)
+    };
+    // Angolo in [0, 2π) partendo da sinistra in senso orario sullo schermo (y verso il basso)
+    let angle = |(x, y): (f32, f32)| (y.atan2(x) + PI) % (2.0 * PI);
+
+    match order {
+        BucketOrder::CenterOut => buckets.sort_by(|a, b| {
+            let (a, b) = (offset(a), offset(b));
+            let distance = |(x, y): (f32, f32)| x * x + y * y;
+            distance(a).partial_cmp(&distance(b)).unwrap_or(std::cmp::Ordering::Equal)
+                .then(angle(a).partial_cmp(&angle(b)).unwrap_or(std::cmp::Ordering::Equal))
+        }),
+        BucketOrder::Spiral => {
+            let (columns, rows) = buckets.iter().map(&cell).fold((1, 1), |(w, h), (x, y)| (w.max(x + 1), h.max(y + 1)));
+            let center = (
+                ((center_x - origin_x) / bucket_width).min(columns - 1),
+                ((center_y - origin_y) / bucket_height).min(rows - 1),
+            );
+            let steps = spiral_steps(columns, rows, center);
+            buckets.sort_by_key(|bucket| {
+                let (x, y) = cell(bucket);
+                steps[(y * columns + x) as usize]
+            });
+        },
+        BucketOrder::Scanline => buckets.sort_by_key(|bucket| (bucket.rect.y, bucket.rect.x)),
+        BucketOrder::Hilbert => {
+            let (columns, rows) = buckets.iter().map(&cell).fold((1, 1), |(w, h), (x, y)| (w.max(x + 1), h.max(y + 1)));
+            let side = columns.max(rows).next_power_of_two();
+            buckets.sort_by_key(|bucket| {
+                let (x, y) = cell(bucket);
+                hilbert_index(side, x, y)
+            });
+        },
+        BucketOrder::Random => buckets.shuffle(&mut StdRng::seed_from_u64(seed as u64)),
+    }
+}
+
+// Passo in cui una spirale che parte da `center` raggiunge ogni cella di una griglia
+// columns x rows, indicizzata per righe. La spirale gira in senso orario sullo schermo con lati
+// lunghi 1, 1, 2, 2, 3, 3, ...: ogni anello riprende dalla cella in cui è finito il precedente.
+// Le celle fuori dalla griglia vengono saltate.
+fn spiral_steps(columns: u32, rows: u32, center: (u32, u32)) -> Vec<u32> {
+    let total = columns * rows;
+    let mut steps = vec![0; total as usize];
+    let (mut x, mut y) = (center.0 as i64, center.1 as i64);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut visited = 0;
+    let mut side = 1;
+    let mut turn = 0;
+    loop {
+        for _ in 0..side {
+            if x >= 0 && y >= 0 && x < columns as i64 && y < rows as i64 {
+                steps[(y as u32 * columns + x as u32) as usize] = visited;
+                visited += 1;
+                if visited == total {
+                    return steps;
+                }
+            }
+            let (dx, dy) = directions[turn % 4];
+            x += dx;
+            y += dy;
+        }
+        turn += 1;
+        if turn % 2 == 0 {
+            side += 1;
+        }
+    }
+}
+
+// Posizione di (x, y) lungo la curva di Hilbert che riempie una griglia side x side (side potenza di 2)
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0u64;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Ruota il quadrante perché la curva resti continua
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mistest::test_scene;
+    use std::collections::HashSet;
+
+    const WIDTH: u32 = 32;
+    const HEIGHT: u32 = 18;
+
+    // Pixel e campioni per pixel di una piccola immagine della scena di prova resa con `num_threads` worker
+    fn render_pixels(num_threads: usize, order: BucketOrder) -> (Vec<u8>, Vec<u32>) {
+        let scene = test_scene(10.0, 0.3);
+        let camera = BaseCamera::new(
+            Vector3::new(0.0, 12.0, -60.0),
+            Vector3::new(0.0, 0.0, 15.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            WIDTH as f32 / HEIGHT as f32,
+            0.0,
+            1.0,
+        );
+        let scene_settings = SceneSettings::new();
+        let rect = Rect { x: 0, y: 0, width: WIDTH, height: HEIGHT };
+        let buckets = Mutex::new(generate_buckets(WIDTH, HEIGHT, rect, 6, order, 0));
+        let image = Mutex::new((vec![0u8; (WIDTH * HEIGHT * 4) as usize], vec![0u32; (WIDTH * HEIGHT) as usize]));
+        let context = RenderContext::new(WIDTH, HEIGHT, &camera, &scene, &scene_settings);
+        render_buckets(&buckets, context, num_threads, &CancelToken::new(), |bucket, sample_counts| {
+            let mut image = image.lock().unwrap();
+            let (image_data, image_counts) = &mut *image;
+            copy_bucket(bucket, WIDTH, image_data);
+            for (i, &samples) in sample_counts.iter().enumerate() {
+                let x = bucket.rect.x + i as u32 % bucket.rect.width;
+                let y = bucket.rect.y + i as u32 / bucket.rect.width;
+                image_counts[(y * WIDTH + x) as usize] = samples;
+            }
+        });
+        image.into_inner().unwrap()
+    }
+
+    #[test]
+    fn render_does_not_depend_on_thread_count() {
+        let single = render_pixels(1, BucketOrder::Scanline);
+        assert!(single.0.iter().any(|&value| value != 0 && value != 255), "immagine vuota");
+        assert_eq!(single, render_pixels(4, BucketOrder::Scanline));
+        assert_eq!(single, render_pixels(4, BucketOrder::Hilbert));
+    }
+
+    #[test]
+    fn hilbert_index_visits_adjacent_cells() {
+        let side = 8;
+        let mut cells = vec![(0, 0); (side * side) as usize];
+        for y in 0..side {
+            for x in 0..side {
+                cells[hilbert_index(side, x, y) as usize] = (x, y);
+            }
+        }
+        // Ogni indice compare una sola volta e celle consecutive sono vicine
+        assert_eq!(cells.iter().collect::<HashSet<_>>().len(), cells.len());
+        for pair in cells.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1, "{:?} -> {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn spiral_steps_start_at_center_and_cover_the_grid() {
+        let steps = spiral_steps(5, 3, (2, 1));
+        assert_eq!(steps[5 + 2], 0);  // riga 1, colonna 2
+        let mut sorted = steps.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..15).collect::<Vec<_>>());
+
+        // In una griglia quadrata con il centro nel mezzo la spirale non esce mai dalla griglia
+        let steps = spiral_steps(5, 5, (2, 2));
+        let mut cells = [(0, 0); 25];
+        for (index, &step) in steps.iter().enumerate() {
+            cells[step as usize] = (index as u32 % 5, index as u32 / 5);
+        }
+        for pair in cells.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1, "{:?} -> {:?}", a, b);
+        }
+    }
+}
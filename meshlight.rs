@@ -0,0 +1,209 @@
+use crate::vector3::Vector3;
+use crate::baseobject::BaseObject;
+use crate::baselight::{AGColor, LightSample};
+use crate::boundingbox::Boundingbox;
+
+// Tabella di Walker/Vose: sceglie un indice con probabilità proporzionale al suo peso in O(1)
+#[derive(Debug)]
+pub struct AliasTable {
+    prob: Vec<f32>,
+    alias: Vec<usize>,
+    pdf: Vec<f32>,
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f32]) -> Self {
+        let count = weights.len();
+        let total: f32 = weights.iter().sum();
+        let pdf: Vec<f32> = if total > 0.0 {
+            weights.iter().map(|w| w / total).collect()
+        } else {
+            vec![1.0 / count as f32; count]
+        };
+
+        let mut prob = vec![0.0; count];
+        let mut alias = vec![0; count];
+        let mut scaled: Vec<f32> = pdf.iter().map(|p| p * count as f32).collect();
+        let mut small: Vec<usize> = Vec::new();
+        let mut large: Vec<usize> = Vec::new();
+        for (i, &p) in scaled.iter().enumerate() {
+            if p < 1.0 { small.push(i) } else { large.push(i) }
+        }
+
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] = (scaled[l] + scaled[s]) - 1.0;
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Gli avanzi (per errori di arrotondamento) hanno probabilità piena
+        for i in large.into_iter().chain(small) {
+            prob[i] = 1.0;
+            alias[i] = i;
+        }
+
+        AliasTable { prob, alias, pdf }
+    }
+
+    // Indice scelto con u in [0, 1)
+    pub fn sample(&self, u: f32) -> usize {
+        let scaled = u * self.prob.len() as f32;
+        let index = (scaled as usize).min(self.prob.len() - 1);
+        let remainder = scaled - index as f32;
+        if remainder < self.prob[index] { index } else { self.alias[index] }
+    }
+
+    // Probabilità discreta dell'indice
+    pub fn pdf(&self, index: usize) -> f32 {
+        self.pdf[index]
+    }
+}
+
+// I triangoli di un BaseObject con materiale emissivo, usati come sorgente di luce.
+// I triangoli sono scelti in proporzione all'area con la tabella alias, poi il punto è
+// uniforme sul triangolo: la pdf in area è pdf(triangolo) / area del triangolo.
+#[derive(Debug)]
+pub struct MeshLight {
+    pub emission: AGColor,
+    pub bbox: Boundingbox,  // dei soli triangoli emissivi, per il LightTree
+    triangles: Vec<usize>,  // indici in BaseObject::vadr, in ordine crescente
+    table: AliasTable,
+    total_area: f32,
+}
+
+impl MeshLight {
+    // `triangles` sono gli indici dei triangoli che emettono `emission`; None se hanno area nulla
+    pub fn from_triangles(obj: &BaseObject, triangles: &[usize], emission: AGColor) -> Option<MeshLight> {
+        let mut sorted = triangles.to_vec();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let mut kept = Vec::new();
+        let mut areas = Vec::new();
+        let mut bbox = Boundingbox::new_empty();
+        for index in sorted {
+            let area = triangle_area(obj, index);
+            if area > 0.0 {
+                kept.push(index);
+                areas.push(area);
+                bbox.expand(&obj.tri_bbox[index]);
+            }
+        }
+        if kept.is_empty() {
+            return None;
+        }
+        let total_area = areas.iter().sum();
+        Some(MeshLight {
+            emission,
+            bbox,
+            triangles: kept,
+            table: AliasTable::new(&areas),
+            total_area,
+        })
+    }
+
+    pub fn total_area(&self) -> f32 {
+        self.total_area
+    }
+
+    // Radianza * area: moltiplicata per cos / d² dà l'irradianza, nella stessa scala
+    // di luminanza * intensità * PI delle BaseLight (vedi LightTree)
+    pub fn power(&self) -> f32 {
+        self.emission.luminance() * self.total_area
+    }
+
+    // Campiona un punto della mesh visto da `point`. I triangoli emettono dal lato
+    // della loro normale geometrica.
+    pub fn sample(&self, obj: &BaseObject, point: &Vector3, u0: f32, u1: f32, u2: f32) -> Option<LightSample> {
+        let slot = self.table.sample(u0);
+        let triangle_index = self.triangles[slot];
+        let triangle = &obj.vadr[triangle_index];
+        let (a, b, c) = (obj.padr[triangle.a], obj.padr[triangle.b], obj.padr[triangle.c]);
+
+        // Punto uniforme sul triangolo
+        let su = u1.sqrt();
+        let position = a * (1.0 - su) + b * (su * (1.0 - u2)) + c * (su * u2);
+
+        let to_light = position - *point;
+        let distance_squared = to_light.length_squared();
+        if distance_squared <= 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+        let weight = self.slot_weight(obj, slot, &direction, distance);
+        if weight <= 0.0 {
+            return None;
+        }
+        Some(LightSample { direction, distance, weight })
+    }
+
+    // Inverso della pdf in angolo solido con cui sample() genererebbe la direzione che
+    // colpisce il triangolo a distanza `distance`; zero se il triangolo non fa parte della
+    // luce o non emette verso l'origine
+    pub fn weight(&self, obj: &BaseObject, triangle_index: usize, direction: &Vector3, distance: f32) -> f32 {
+        match self.triangles.binary_search(&triangle_index) {
+            Ok(slot) => self.slot_weight(obj, slot, direction, distance),
+            Err(_) => 0.0,
+        }
+    }
+
+    fn slot_weight(&self, obj: &BaseObject, slot: usize, direction: &Vector3, distance: f32) -> f32 {
+        let triangle_index = self.triangles[slot];
+        let triangle = &obj.vadr[triangle_index];
+        let (a, b, c) = (obj.padr[triangle.a], obj.padr[triangle.b], obj.padr[triangle.c]);
+        let cross = (b - a).cross(&(c - a));
+        let normal = cross.normalize();
+        let normal = if normal.dot(&obj.norm[triangle_index]) < 0.0 { -normal } else { normal };
+        let cos_emission = normal.dot(&-*direction);
+        let pdf = self.table.pdf(slot);
+        if cos_emission <= 0.0 || distance <= 0.0 || pdf <= 0.0 {
+            return 0.0;
+        }
+        // pdf in area = pdf(triangolo) / area, convertita in angolo solido con d² / cos
+        let area = 0.5 * cross.length();
+        cos_emission * area / (pdf * distance * distance)
+    }
+}
+
+fn triangle_area(obj: &BaseObject, index: usize) -> f32 {
+    let triangle = &obj.vadr[index];
+    let (a, b, c) = (obj.padr[triangle.a], obj.padr[triangle.b], obj.padr[triangle.c]);
+    0.5 * (b - a).cross(&(c - a)).length()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alias_table_follows_weights() {
+        let weights = [1.0, 0.0, 3.0, 4.0];
+        let table = AliasTable::new(&weights);
+        let total: f32 = weights.iter().sum();
+        for (index, weight) in weights.iter().enumerate() {
+            assert!((table.pdf(index) - weight / total).abs() < 1e-6);
+        }
+
+        // Con u uniforme ogni indice è scelto con la sua probabilità
+        let draws = 8000;
+        let mut counts = [0; 4];
+        for i in 0..draws {
+            counts[table.sample((i as f32 + 0.5) / draws as f32)] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        for (index, &count) in counts.iter().enumerate() {
+            assert!((count as f32 / draws as f32 - table.pdf(index)).abs() < 1e-3, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn alias_table_without_weights_is_uniform() {
+        let table = AliasTable::new(&[0.0, 0.0]);
+        assert_eq!(table.pdf(0), 0.5);
+        assert_eq!(table.sample(0.25), 0);
+        assert_eq!(table.sample(0.75), 1);
+    }
+}
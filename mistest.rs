@@ -0,0 +1,154 @@
+use crate::vector3::Vector3;
+use crate::baseobject::{BaseObject, Triangle};
+use crate::boundingbox::Boundingbox;
+use crate::baselight::{AGColor, AreaShape, BaseLight, LightType};
+use crate::basematerial::BaseMaterial;
+use crate::basecamera::BaseCamera;
+use crate::scene::Scene;
+use crate::scenesettings::SceneSettings;
+use crate::compute_radiance;
+use crate::sampler::{Sampler, SamplerType};
+use rayon::prelude::*;
+
+const TEST_WIDTH: u32 = 192;
+const TEST_HEIGHT: u32 = 108;
+const TEST_RENDERS: usize = 8;  // render indipendenti da cui si stima la varianza per pixel
+
+// Scena di prova per il multiple importance sampling, sul modello di quella di Veach:
+// un piano lucido che riflette una luce sferica, con luce piccola e grande e con
+// roughness da quasi specchio a opaca. Per ogni caso si rende più volte la stessa
+// immagine senza e con MIS, a parità di campioni per punto, e si confronta la varianza
+// media dei pixel; le medie delle due tecniche devono coincidere, visto che entrambe
+// sono non distorte. Si avvia con `--mis-test`; le immagini medie finiscono in mis_*.png.
+pub fn run_mis_comparison() {
+    let light_sizes = [("piccola", 2.0), ("grande", 40.0)];
+    let roughness_values = [0.05, 0.2, 0.5, 0.9];
+
+    println!("{:<10} {:>10} {:>12} {:>12} {:>14} {:>14} {:>10}", "luce", "roughness", "media no MIS", "media MIS", "var. no MIS", "var. MIS", "riduzione");
+    for (size_name, diameter) in light_sizes {
+        for roughness in roughness_values {
+            let scene = test_scene(diameter, roughness);
+            let camera = BaseCamera::new(
+                Vector3::new(0.0, 12.0, -60.0),
+                Vector3::new(0.0, 0.0, 15.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                40.0,
+                TEST_WIDTH as f32 / TEST_HEIGHT as f32,
+                0.0,
+                1.0,
+            );
+
+            // Stesso budget: 8 campioni di luce, oppure 4 di luce + 4 della BRDF.
+            // Campioni indipendenti, altrimenti la varianza fra i render non sarebbe quella vera.
+            let mut without_mis = SceneSettings::new();
+            without_mis.ao_enabled = false;
+            without_mis.sampler = SamplerType::Independent;
+            without_mis.mis_enabled = false;
+            without_mis.max_samples_light = 8;
+            let mut with_mis = SceneSettings::new();
+            with_mis.ao_enabled = false;
+            with_mis.sampler = SamplerType::Independent;
+            with_mis.mis_enabled = true;
+            with_mis.max_samples_light = 4;
+            with_mis.max_samples_bsdf = 4;
+
+            let (mean_off, variance_off) = measure(&camera, &scene, &without_mis);
+            let (mean_on, variance_on) = measure(&camera, &scene, &with_mis);
+            let reduction = if variance_on > 0.0 { variance_off / variance_on } else { f32::INFINITY };
+            println!(
+                "{:<10} {:>10.2} {:>12.5} {:>12.5} {:>14.6} {:>14.6} {:>9.2}x",
+                size_name, roughness, average_luminance(&mean_off), average_luminance(&mean_on), variance_off, variance_on, reduction
+            );
+
+            save_image(&format!("mis_{}_{:.2}_off.png", size_name, roughness), &mean_off);
+            save_image(&format!("mis_{}_{:.2}_on.png", size_name, roughness), &mean_on);
+        }
+    }
+}
+
+// Piano quadrato lucido in y = 0 e una luce sferica sopra il fondo del piano
+pub fn test_scene(light_diameter: f32, roughness: f32) -> Scene {
+    let half = 80.0;
+    let mut obj = BaseObject::new("Piano".to_string(), String::new());
+    obj.padr = vec![
+        Vector3::new(-half, 0.0, -half),
+        Vector3::new(half, 0.0, -half),
+        Vector3::new(half, 0.0, half),
+        Vector3::new(-half, 0.0, half),
+    ];
+    for (a, b, c) in [(0, 2, 1), (0, 3, 2)] {
+        obj.vadr.push(Triangle::new(a, b, c));
+        obj.norm.push(Vector3::new(0.0, 1.0, 0.0));
+        obj.tri_bbox.push(Boundingbox::from_triangle(obj.padr[a], obj.padr[b], obj.padr[c]));
+    }
+    obj.boundingbox = Some(Boundingbox::new(Vector3::new(-half, 0.0, -half), Vector3::new(half, 0.0, half)));
+    obj.build_bvh();
+
+    let mut light = BaseLight::new(
+        "Sfera".to_string(),
+        Vector3::new(0.0, 30.0, 70.0),
+        Vector3::new(0.0, -1.0, 0.0),
+        AGColor::new(1.0, 1.0, 1.0),
+        0.1,  // bassa, così i riflessi non saturano nelle immagini salvate
+        LightType::Area,
+    );
+    light.area_size = (light_diameter, light_diameter);
+    light.area_shape = AreaShape::Sphere;
+
+    let material = BaseMaterial::new("Lucido".to_string(), AGColor::new(0.2, 0.2, 0.2), 1.0, roughness);
+    Scene::new(obj, vec![light], vec![material])
+}
+
+// Media per pixel su TEST_RENDERS render e varianza della luminanza, mediata sull'immagine
+fn measure(camera: &BaseCamera, scene: &Scene, scene_settings: &SceneSettings) -> (Vec<AGColor>, f32) {
+    let num_pixels = (TEST_WIDTH * TEST_HEIGHT) as usize;
+    let renders: Vec<Vec<AGColor>> = (0..TEST_RENDERS)
+        .map(|render| {
+            (0..num_pixels)
+                .into_par_iter()
+                .map(|i| {
+                    let (px, py) = (i as u32 % TEST_WIDTH, i as u32 / TEST_WIDTH);
+                    // Ogni render è un campione diverso della stessa sequenza del pixel
+                    let mut sampler = Sampler::new(scene_settings.sampler, TEST_RENDERS as u32, scene_settings.seed);
+                    sampler.start_pixel_sample(px, py, render as u32);
+                    let x = px as f32 + 0.5;
+                    let y = py as f32 + 0.5;
+                    let ray = camera.get_ray_sampled(x / TEST_WIDTH as f32, 1.0 - y / TEST_HEIGHT as f32, sampler.get_2d());
+                    compute_radiance(&ray, scene, scene_settings, &mut sampler)
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut mean = vec![AGColor::black(); num_pixels];
+    let mut variance = 0.0;
+    for i in 0..num_pixels {
+        let mut sum = AGColor::black();
+        let mut luminance_sum = 0.0;
+        let mut luminance_squared_sum = 0.0;
+        for render in &renders {
+            sum += render[i];
+            let luminance = render[i].luminance();
+            luminance_sum += luminance;
+            luminance_squared_sum += luminance * luminance;
+        }
+        let n = TEST_RENDERS as f32;
+        mean[i] = sum * (1.0 / n);
+        let luminance_mean = luminance_sum / n;
+        variance += (luminance_squared_sum / n - luminance_mean * luminance_mean).max(0.0) * n / (n - 1.0);
+    }
+    (mean, variance / num_pixels as f32)
+}
+
+fn average_luminance(pixels: &[AGColor]) -> f32 {
+    pixels.iter().map(|c| c.luminance()).sum::<f32>() / pixels.len() as f32
+}
+
+fn save_image(filename: &str, pixels: &[AGColor]) {
+    let bytes: Vec<u8> = pixels.iter()
+        .flat_map(|c| [c.r, c.g, c.b].map(|v| (v.clamp(0.0, 1.0) * 255.0) as u8))
+        .collect();
+    if let Err(e) = image::save_buffer(filename, &bytes, TEST_WIDTH, TEST_HEIGHT, image::ColorType::Rgb8) {
+        println!("Impossibile salvare {}: {}", filename, e);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::vector3::Vector3;
+
+// Rumore di gradiente di Perlin 3D senza tabelle: i gradienti ai vertici del reticolo
+// sono scelti con un hash intero, così il rumore è identico su ogni thread.
+
+#[inline]
+fn hash3(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+// Gradiente fra i 12 spigoli del cubo, come nell'Improved Noise di Perlin
+#[inline]
+fn grad(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+#[inline]
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline]
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Rumore di Perlin in [-1, 1] circa
+pub fn perlin(p: &Vector3) -> f32 {
+    let xf = p.x.floor();
+    let yf = p.y.floor();
+    let zf = p.z.floor();
+    let (xi, yi, zi) = (xf as i32, yf as i32, zf as i32);
+    let (x, y, z) = (p.x - xf, p.y - yf, p.z - zf);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        grad(hash3(xi + dx, yi + dy, zi + dz), x - dx as f32, y - dy as f32, z - dz as f32)
+    };
+
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+// Fractional Brownian motion: somma di ottave di rumore a frequenza crescente
+pub fn fbm(p: &Vector3, octaves: u32, lacunarity: f32, gain: f32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut norm = 0.0;
+    for _ in 0..octaves.max(1) {
+        sum += perlin(&(*p * frequency)) * amplitude;
+        norm += amplitude;
+        amplitude *= gain;
+        frequency *= lacunarity;
+    }
+    sum / norm
+}
+
+// Come fbm ma con il valore assoluto di ogni ottava (venature del marmo)
+pub fn turbulence(p: &Vector3, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut norm = 0.0;
+    for _ in 0..octaves.max(1) {
+        sum += perlin(&(*p * frequency)).abs() * amplitude;
+        norm += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / norm
+}
@@ -0,0 +1,88 @@
+use crate::baseray::BaseRay;
+use crate::baselight::AGColor;
+use crate::basematerial::MaterialType;
+use crate::scene::Scene;
+use crate::scenesettings::SceneSettings;
+use crate::{background, direct_lighting, offset_ray_origin, resolve_material};
+use crate::sampler::Sampler;
+
+// Path tracer unidirezionale: a ogni rimbalzo somma la luce diretta (next-event estimation
+// con le luci della scena) e prosegue in una direzione campionata dalla BRDF.
+// La roulette russa termina i cammini che portano poca energia.
+pub fn trace_path(
+    ray: &BaseRay,
+    scene: &Scene,
+    scene_settings: &SceneSettings,
+    sampler: &mut Sampler,
+) -> AGColor {
+    let obj = &scene.obj;
+    let mut radiance = AGColor::black();
+    let mut throughput = AGColor::new(1.0, 1.0, 1.0);
+    let mut ray = ray.clone();
+    let mut path_length = 0.0;
+    // L'ambiente e gli oggetti emissivi sono già campionati dalla next-event estimation: quando
+    // un raggio li raggiunge si aggiungono solo se nessuno li ha campionati, cioè dalla camera
+    // o dopo un rimbalzo speculare.
+    let mut specular_bounce = true;
+
+    for bounce in 0..scene_settings.max_depth {
+        let hit = obj.bvh_root.as_ref().and_then(|bvh_node| bvh_node.find_nearest_intersection(&ray, obj));
+        let (distance, triangle_index) = match hit {
+            Some(hit) => hit,
+            None => {
+                if specular_bounce && (bounce > 0 || scene_settings.env_visible) {
+                    radiance += throughput * background(&ray.direction, scene, scene_settings);
+                }
+                break;
+            }
+        };
+
+        path_length += distance;
+        let mut hit = obj.surface_hit(&ray, distance, triangle_index);
+        let material = resolve_material(scene, &mut hit, path_length * scene_settings.pixel_spread_angle);
+        let material = material.as_ref();
+        let (hit_point, normal, front_face) = (hit.point, hit.normal, hit.front_face);
+        let view_dir = -ray.direction;
+        let u = sampler.get_3d();
+
+        if specular_bounce {
+            radiance += throughput * material.emitted(front_face);
+        }
+
+        specular_bounce = material.is_specular();
+        let (new_dir, weight) = if specular_bounce {
+            // Assorbimento di Beer-Lambert lungo il tratto appena percorso dentro il materiale
+            if material.material_type == MaterialType::Glass && !front_face {
+                throughput *= material.transmittance(distance);
+            }
+            // Lobi delta: la luce diretta non si può campionare, si segue solo il rimbalzo
+            match material.sample_specular(&normal, &view_dir, front_face, u) {
+                Some(sample) => sample,
+                None => break,
+            }
+        } else {
+            // Next-event estimation
+            radiance += throughput * direct_lighting(hit_point, normal, view_dir, material, scene, scene_settings, sampler);
+
+            // Nuova direzione campionata dalla BRDF (coseno per il diffuso, GGX per lo speculare)
+            match material.sample(&normal, &view_dir, u) {
+                Some((dir, weight, _pdf)) => (dir, weight),
+                None => break,
+            }
+        };
+        throughput *= weight;
+
+        // Roulette russa
+        if bounce + 1 >= scene_settings.rr_start_depth {
+            let survive = throughput.max_component().min(0.95);
+            if survive <= 0.0 || sampler.get_1d() >= survive {
+                break;
+            }
+            throughput *= 1.0 / survive;
+        }
+
+        ray = BaseRay::new(offset_ray_origin(hit_point, normal, new_dir), new_dir);
+    }
+
+    radiance
+}
@@ -0,0 +1,162 @@
+use crate::scene::RenderContext;
+use crate::film::{Film, PixelFilter};
+use crate::sampler::Sampler;
+use crate::baselight::AGColor;
+use crate::cancel::CancelToken;
+use crate::checkpoint::{Checkpoint, scene_hash};
+use crate::compute_radiance;
+use rayon::prelude::*;
+use std::io::ErrorKind;
+use std::time::Instant;
+
+const CHUNK_ROWS: u32 = 16;  // righe renderizzate prima di versare i campioni nella pellicola
+
+// Render progressivo: ogni passata porta tutti i pixel al doppio dei campioni della passata
+// precedente (1, 2, 4, 8, ...) fino a progressive_samples, accumulandoli nella stessa pellicola,
+// e chiama `on_pass` con la pellicola e lo stato. Ogni checkpoint_interval secondi, e alla fine
+// anche se il render è stato annullato, salva un checkpoint; con `resume` riparte da quello
+// salvato se corrisponde alla scena. Un checkpoint da cui non si è ripartiti viene sovrascritto
+// solo se è della stessa scena e ha meno campioni. Restituisce la pellicola e i campioni per pixel.
+pub fn render_passes<F>(
+    context: RenderContext,
+    num_threads: usize,
+    cancel: &CancelToken,
+    resume: bool,
+    mut on_pass: F,
+) -> (Film, Vec<u32>)
+where
+    F: FnMut(&Film, String),
+{
+    let RenderContext { width, height, camera, scene, scene_settings } = context;
+    let target_samples = scene_settings.progressive_samples.max(1);
+    let hash = scene_hash(camera, scene, scene_settings);
+    let mut film = Film::new(width, height, PixelFilter::new(scene_settings.pixel_filter, scene_settings.filter_radius));
+    let mut sample_counts = vec![0u32; (width * height) as usize];
+    // Un checkpoint già presente, se non lo si riprende, può essere di un altro render da
+    // riprendere più tardi: lo si legge una volta sola qui, se si salvano checkpoint, e lo si
+    // sostituisce solo con uno della stessa scena che abbia almeno i suoi campioni
+    let mut required_samples = None;
+    if resume || scene_settings.checkpoint_interval > 0 {
+        match Checkpoint::load(&scene_settings.checkpoint_file, width, height) {
+            Ok(checkpoint) if checkpoint.scene_hash == hash && resume => {
+                film.restore(checkpoint.sums);
+                sample_counts = checkpoint.sample_counts;
+                println!(
+                    "Ripreso il checkpoint '{}': almeno {} campioni per pixel",
+                    scene_settings.checkpoint_file, sample_counts.iter().min().unwrap_or(&0)
+                );
+            },
+            Ok(checkpoint) if checkpoint.scene_hash == hash => required_samples = Some(total_samples(&checkpoint.sample_counts)),
+            Ok(_) => {
+                if resume {
+                    println!("Il checkpoint '{}' è di un'altra scena: si riparte da zero", scene_settings.checkpoint_file);
+                }
+                required_samples = Some(u64::MAX);
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                if resume {
+                    println!("Il checkpoint '{}' non esiste: si riparte da zero", scene_settings.checkpoint_file);
+                }
+            },
+            Err(e) => {
+                if resume {
+                    println!("Impossibile leggere il checkpoint '{}': {}", scene_settings.checkpoint_file, e);
+                }
+                required_samples = Some(u64::MAX);
+            },
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .expect("Impossibile creare il pool di thread");
+    let mut save_checkpoint = |film: &Film, sample_counts: &[u32]| {
+        if let Some(required) = required_samples {
+            if total_samples(sample_counts) < required {
+                println!(
+                    "Il checkpoint '{}' è di un altro render: non viene sovrascritto (riprendilo con --resume o cancellalo)",
+                    scene_settings.checkpoint_file
+                );
+                return;
+            }
+            required_samples = None;
+        }
+        match Checkpoint::new(hash, film, sample_counts).save(&scene_settings.checkpoint_file) {
+            Ok(()) => println!("Checkpoint salvato in '{}'", scene_settings.checkpoint_file),
+            Err(e) => println!("Impossibile salvare il checkpoint: {}", e),
+        }
+    };
+    let mut pass = 0;
+    let start = Instant::now();
+    let mut last_checkpoint = Instant::now();
+
+    loop {
+        let samples_done = *sample_counts.iter().min().unwrap_or(&target_samples);
+        if samples_done >= target_samples || cancel.is_cancelled() {
+            break;
+        }
+        let pass_target = (samples_done * 2).max(1).min(target_samples);
+        for chunk_start in (0..height).step_by(CHUNK_ROWS as usize) {
+            if !cancel.proceed() {
+                break;
+            }
+            let chunk_end = (chunk_start + CHUNK_ROWS).min(height);
+            let pixels = (chunk_start * width) as usize..(chunk_end * width) as usize;
+            // Ogni pixel riprende dal primo campione che non ha ancora, anche dopo un checkpoint
+            // salvato a metà passata. La pausa e l'annullamento si controllano a ogni campione:
+            // un pixel interrotto conta solo i campioni calcolati davvero.
+            let chunk_samples: Vec<Vec<(f32, f32, AGColor)>> = pool.install(|| {
+                pixels.clone()
+                    .into_par_iter()
+                    .map(|i| {
+                        let (x, y) = (i as u32 % width, i as u32 / width);
+                        let mut sampler = Sampler::new(scene_settings.sampler, target_samples, scene_settings.seed);
+                        (sample_counts[i]..pass_target)
+                            .map_while(|sample_index| {
+                                if !cancel.proceed() {
+                                    return None;
+                                }
+                                sampler.start_pixel_sample(x, y, sample_index);
+                                let (jitter_x, jitter_y) = sampler.get_2d();
+                                let film_x = x as f32 + jitter_x;
+                                let film_y = y as f32 + jitter_y;
+                                let ray = camera.get_ray_sampled(film_x / width as f32, film_y / height as f32, sampler.get_2d());
+                                Some((film_x, film_y, compute_radiance(&ray, scene, scene_settings, &mut sampler)))
+                            })
+                            .collect()
+                    })
+                    .collect()
+            });
+            for (i, samples) in pixels.zip(&chunk_samples) {
+                for &(film_x, film_y, color) in samples {
+                    film.add_sample(film_x, film_y, color);
+                }
+                sample_counts[i] += samples.len() as u32;
+            }
+
+            if scene_settings.checkpoint_interval > 0 && last_checkpoint.elapsed().as_secs() >= scene_settings.checkpoint_interval as u64 {
+                save_checkpoint(&film, &sample_counts);
+                last_checkpoint = Instant::now();
+            }
+        }
+        // Una passata interrotta lascia pixel con un numero diverso di campioni: la si mostra
+        // comunque, visto che la pellicola normalizza ogni pixel con la somma dei suoi pesi
+        if !cancel.is_cancelled() {
+            pass += 1;
+            println!("Passata {} completata: {} campioni per pixel in {:?}", pass, pass_target, start.elapsed());
+        }
+        let samples_done = *sample_counts.iter().min().unwrap_or(&0);
+        on_pass(&film, format!("Passata {} - {} campioni per pixel", pass, samples_done));
+    }
+
+    // Anche dopo Stop o Ctrl+C, così il render si può riprendere con --resume
+    if scene_settings.checkpoint_interval > 0 {
+        save_checkpoint(&film, &sample_counts);
+    }
+    (film, sample_counts)
+}
+
+fn total_samples(sample_counts: &[u32]) -> u64 {
+    sample_counts.iter().map(|&samples| samples as u64).sum()
+}
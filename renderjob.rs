@@ -0,0 +1,223 @@
+use crate::basecamera::BaseCamera;
+use crate::scene::{Scene, RenderContext};
+use crate::scenesettings::{SceneSettings, RenderMode};
+use crate::bucket::{Bucket, BucketState, Rect};
+use crate::cancel::CancelToken;
+use crate::progressive::render_passes;
+use crate::{copy_bucket, generate_buckets, render_buckets};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+// Stato condiviso fra il thread di render e l'interfaccia
+pub struct RenderJobState {
+    pub framebuffer: Vec<u8>,  // immagine RGBA corrente
+    pub dirty: bool,           // framebuffer cambiato dall'ultima lettura dell'interfaccia
+    pub status: String,
+    pub finished: bool,
+}
+
+// Render in un thread separato, così l'interfaccia resta reattiva e mostra l'immagine mentre
+// cresce. Con RenderMode::Progressive l'immagine viene aggiornata alla fine di ogni passata di
+// progressive::render_passes; con RenderMode::Buckets ogni bucket finito viene copiato
+// nell'immagine. L'interfaccia legge l'immagine con poll(), può fermare il render con stop()
+// e passarlo al render successivo, che ne attende la fine, o sospenderlo con pause() e resume().
+pub struct RenderJob {
+    width: u32,
+    shared: Arc<JobShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Ciò che il thread di render condivide con l'interfaccia
+struct JobShared {
+    state: Mutex<RenderJobState>,
+    buckets: Mutex<Vec<Bucket>>,  // vuoto in modalità progressiva
+    cancel: CancelToken,
+}
+
+impl RenderJob {
+    // Carica la scena con `load` nel thread di render; con `resume` il render progressivo
+    // riparte dal checkpoint. Se c'è un render precedente, già fermato con stop(), il nuovo
+    // thread ne attende la fine prima di iniziare: altrimenti i due scriverebbero gli stessi
+    // file di output e di checkpoint. L'attesa non blocca chi chiama start().
+    pub fn start<F>(width: u32, height: u32, num_threads: usize, resume: bool, previous: Option<RenderJob>, load: F) -> Self
+    where
+        F: FnOnce() -> (BaseCamera, Scene, SceneSettings) + Send + 'static,
+    {
+        let status = if previous.is_some() { "Attesa della fine del render precedente..." } else { "Caricamento della scena..." };
+        let shared = Arc::new(JobShared {
+            state: Mutex::new(RenderJobState {
+                framebuffer: vec![0; (width * height * 4) as usize],
+                dirty: true,
+                status: status.to_string(),
+                finished: false,
+            }),
+            buckets: Mutex::new(Vec::new()),
+            cancel: CancelToken::new(),
+        });
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || {
+            if let Some(previous) = previous {
+                previous.wait();
+                let mut state = thread_shared.state.lock().unwrap();
+                state.status = "Caricamento della scena...".to_string();
+                state.dirty = true;
+            }
+            // Un errore nel caricamento o nel render non deve lasciare l'interfaccia ad aspettare
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                run_job(width, height, num_threads, resume, load, &thread_shared)
+            }));
+            if let Err(payload) = outcome {
+                let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "errore sconosciuto".to_string());
+                let mut state = thread_shared.state.lock().unwrap_or_else(PoisonError::into_inner);
+                state.status = format!("Render fallito: {}", message);
+                state.dirty = true;
+                state.finished = true;
+            }
+        });
+        RenderJob { width, shared, thread: Some(thread) }
+    }
+
+    // Attende che il thread di render termini, dopo stop() o alla fine del render
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    // Chiede al thread di fermarsi: la passata in corso si interrompe al prossimo campione,
+    // a bucket i worker finiscono il bucket corrente
+    pub fn stop(&self) {
+        self.shared.cancel.cancel();
+    }
+
+    // I worker si fermano al prossimo campione o bucket finché non si chiama resume()
+    pub fn pause(&self) {
+        self.shared.cancel.pause();
+        self.shared.state.lock().unwrap().dirty = true;
+    }
+
+    pub fn resume(&self) {
+        self.shared.cancel.resume();
+        self.shared.state.lock().unwrap().dirty = true;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.cancel.is_paused()
+    }
+
+    // Immagine da mostrare, con il contorno dei bucket in lavorazione, e stato del render,
+    // se qualcosa è cambiato; e se il render è finito. I bucket finiti passano a Visualized.
+    pub fn poll(&self) -> (Option<(Vec<u8>, String)>, bool) {
+        let mut in_flight = Vec::new();
+        let mut changed = false;
+        for bucket in self.shared.buckets.lock().unwrap().iter_mut() {
+            match bucket.state {
+                BucketState::StartProcessing | BucketState::EndProcessing => in_flight.push(bucket.rect),
+                BucketState::NotVisualized => {
+                    bucket.state = BucketState::Visualized;
+                    changed = true;
+                },
+                _ => {},
+            }
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.dirty && !changed && in_flight.is_empty() {
+            return (None, state.finished);
+        }
+        state.dirty = false;
+        let mut image = state.framebuffer.clone();
+        for rect in &in_flight {
+            draw_bucket_outline(&mut image, self.width, rect);
+        }
+        let status = if self.shared.cancel.is_paused() && !state.finished {
+            format!("{} - in pausa", state.status)
+        } else {
+            state.status.clone()
+        };
+        (Some((image, status)), state.finished)
+    }
+}
+
+// Corpo del thread di render: carica la scena, la renderizza nella modalità scelta e salva l'immagine
+fn run_job<F>(width: u32, height: u32, num_threads: usize, resume: bool, load: F, shared: &JobShared)
+where
+    F: FnOnce() -> (BaseCamera, Scene, SceneSettings),
+{
+    let (camera, scene, scene_settings) = load();
+    let context = RenderContext::new(width, height, &camera, &scene, &scene_settings);
+    match scene_settings.render_mode {
+        RenderMode::Progressive => {
+            render_passes(context, num_threads, &shared.cancel, resume, |film, status| {
+                let image = film.to_rgba8();
+                let mut state = shared.state.lock().unwrap();
+                state.framebuffer = image;
+                state.dirty = true;
+                state.status = status;
+            });
+        },
+        RenderMode::Buckets => {
+            if resume {
+                println!("--resume vale solo per il render progressivo: il render a bucket riparte da zero");
+            }
+            let rect = Rect { x: 0, y: 0, width, height };
+            *shared.buckets.lock().unwrap() = generate_buckets(width, height, rect, scene_settings.bucket_count, scene_settings.bucket_order, scene_settings.seed);
+            render_job_buckets(context, num_threads, shared);
+        },
+    }
+
+    let mut state = shared.state.lock().unwrap();
+    match image::save_buffer("output.png", &state.framebuffer, width, height, image::ColorType::Rgba8) {
+        Ok(()) => println!("Immagine salvata come 'output.png'"),
+        Err(e) => println!("Impossibile salvare l'immagine: {}", e),
+    }
+    // I bucket mai iniziati di un render interrotto restano NotProcessed
+    for bucket in shared.buckets.lock().unwrap().iter_mut() {
+        if bucket.state != BucketState::NotProcessed {
+            bucket.state = BucketState::Done;
+        }
+    }
+    let outcome = if shared.cancel.is_cancelled() { "interrotto" } else { "completato" };
+    state.status = format!("{} - {}", state.status, outcome);
+    state.dirty = true;
+    state.finished = true;
+}
+
+fn render_job_buckets(context: RenderContext, num_threads: usize, shared: &JobShared) {
+    let total = shared.buckets.lock().unwrap().len();
+    let done = Mutex::new(0);
+    let start = Instant::now();
+    render_buckets(&shared.buckets, context, num_threads, &shared.cancel, |bucket, _| {
+        let mut done = done.lock().unwrap();
+        *done += 1;
+        let mut state = shared.state.lock().unwrap();
+        copy_bucket(bucket, context.width, &mut state.framebuffer);
+        state.dirty = true;
+        state.status = format!("Bucket {} di {}", *done, total);
+    });
+    println!("Rendering completato in {:?}", start.elapsed());
+}
+
+// Contorno bianco di un pixel attorno al bucket, come nei renderer a bucket classici
+fn draw_bucket_outline(image: &mut [u8], width: u32, rect: &Rect) {
+    let height = image.len() as u32 / (width * 4);
+    let mut set = |x: u32, y: u32| {
+        if x < width && y < height {
+            let index = ((y * width + x) * 4) as usize;
+            image[index..index + 4].copy_from_slice(&[255, 255, 255, 255]);
+        }
+    };
+    let (right, bottom) = (rect.x + rect.width - 1, rect.y + rect.height - 1);
+    for x in rect.x..=right {
+        set(x, rect.y);
+        set(x, bottom);
+    }
+    for y in rect.y..=bottom {
+        set(rect.x, y);
+        set(right, y);
+    }
+}
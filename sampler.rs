@@ -0,0 +1,285 @@
+// Generatore dei numeri casuali usati da un campione: jitter del pixel, lente, luci, BRDF, AO.
+// Ogni chiamata a get_1d / get_2d consuma una nuova dimensione; i generatori a bassa
+// discrepanza distribuiscono bene i campioni dello stesso pixel dimensione per dimensione.
+// Ogni valore è funzione solo di (seme, pixel, indice del campione, dimensione), quindi il
+// render è identico a parità di seme, qualunque siano i thread o l'ordine dei bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplerType {
+    Independent,  // numeri pseudo-casuali indipendenti
+    Stratified,   // correlated multi-jittered (Kensler 2013): strati in 2D e nelle proiezioni 1D
+    Sobol,        // Sobol con scrambling di Owen basato su hash (Burley 2020), 2D alla volta
+    BlueNoise,    // sequenza R2 ruotata per pixel con l'interleaved gradient noise (Jimenez 2014)
+}
+
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    pub sampler_type: SamplerType,
+    pub samples_per_pixel: u32,
+    pub seed: u32,
+    pixel: (u32, u32),
+    pixel_seed: u32,
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl Sampler {
+    pub fn new(sampler_type: SamplerType, samples_per_pixel: u32, seed: u32) -> Self {
+        Sampler {
+            sampler_type,
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel: (0, 0),
+            pixel_seed: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Da chiamare prima di ogni campione del pixel (x, y), con sample_index in 0..samples_per_pixel
+    pub fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.pixel_seed = hash(self.seed ^ hash(x ^ hash(y)));
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    pub fn get_1d(&mut self) -> f32 {
+        let dimension = self.next_dimension();
+        let seed = hash(self.pixel_seed ^ hash(dimension));
+        match self.sampler_type {
+            SamplerType::Independent => to_float(hash(seed ^ hash(self.sample_index))),
+            SamplerType::Stratified => {
+                let n = self.samples_per_pixel;
+                let stratum = permute(self.sample_index % n, n, seed);
+                (stratum as f32 + randfloat(self.sample_index, seed.wrapping_mul(0x68bc21eb))) / n as f32
+            },
+            SamplerType::Sobol => {
+                let index = nested_uniform_scramble(self.sample_index, seed);
+                to_float(nested_uniform_scramble(index.reverse_bits(), hash(seed)))
+            },
+            SamplerType::BlueNoise => {
+                let offset = self.interleaved_gradient_noise(dimension);
+                (offset + self.sample_index as f32 * 0.618_034).fract()
+            },
+        }
+    }
+
+    pub fn get_2d(&mut self) -> (f32, f32) {
+        let dimension = self.next_dimension();
+        let seed = hash(self.pixel_seed ^ hash(dimension));
+        match self.sampler_type {
+            SamplerType::Independent => {
+                let first = hash(seed ^ hash(self.sample_index));
+                (to_float(first), to_float(hash(first ^ 0x9e37_79b9)))
+            },
+            SamplerType::Stratified => cmj(self.sample_index % self.samples_per_pixel, self.samples_per_pixel, seed),
+            SamplerType::Sobol => {
+                let index = nested_uniform_scramble(self.sample_index, seed);
+                (
+                    to_float(nested_uniform_scramble(index.reverse_bits(), hash(seed ^ 0x5bd1_e995))),
+                    to_float(nested_uniform_scramble(sobol_second_dimension(index), hash(seed ^ 0x1b87_3593))),
+                )
+            },
+            SamplerType::BlueNoise => {
+                // R2 di Roberts: generalizzazione 2D della sezione aurea
+                let u = self.interleaved_gradient_noise(dimension);
+                let v = self.interleaved_gradient_noise(dimension + 7919);
+                let i = self.sample_index as f32;
+                ((u + i * 0.754_877_7).fract(), (v + i * 0.569_840_3).fract())
+            },
+        }
+    }
+
+    // Tre numeri per BaseMaterial::sample: scelta del lobo e direzione
+    pub fn get_3d(&mut self) -> (f32, f32, f32) {
+        let u = self.get_1d();
+        let (v, w) = self.get_2d();
+        (u, v, w)
+    }
+
+    fn next_dimension(&mut self) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        dimension
+    }
+
+    // Rumore con spettro concentrato sulle alte frequenze: pixel vicini hanno offset lontani.
+    // Ogni dimensione sposta il pattern, così le dimensioni non sono correlate fra loro.
+    fn interleaved_gradient_noise(&self, dimension: u32) -> f32 {
+        let shift = 5.588_238 * (dimension % 64) as f32;
+        let x = self.pixel.0 as f32 + shift;
+        let y = self.pixel.1 as f32 + shift;
+        let noise = (52.982_918 * (0.067_110_56 * x + 0.005_837_15 * y).fract()).fract();
+        // Le dimensioni oltre 64 ripetono il pattern: le si decorrela con un offset casuale,
+        // che dipende anche dal seme per avere render diversi con semi diversi
+        (noise + to_float(hash(self.seed ^ hash(dimension / 64)))).fract()
+    }
+}
+
+// Hash intero a bassa distorsione (lowbias32 di Chris Wellons)
+#[inline]
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+#[inline]
+fn to_float(x: u32) -> f32 {
+    (x >> 8) as f32 * (1.0 / 16_777_216.0)
+}
+
+// Seconda dimensione di Sobol: vettori di direzione v_i = v_(i-1) ^ (v_(i-1) >> 1)
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+// Scrambling di Owen annidato sui bit invertiti (Laine-Karras con l'hash di Burley)
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+// Permutazione pseudo-casuale di 0..l guidata da p (Kensler, "Correlated Multi-Jittered Sampling")
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+fn randfloat(mut i: u32, p: u32) -> f32 {
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb365_34e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc_4795);
+    i ^= 0xdf6e_307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+    to_float(i)
+}
+
+// Campione s di n in 2D, stratificato sia sulla griglia m x n' sia sulle due proiezioni
+fn cmj(s: u32, n: u32, p: u32) -> (f32, f32) {
+    let m = ((n as f32).sqrt() as u32).max(1);
+    let rows = (n + m - 1) / m;
+    let s = permute(s, n, p.wrapping_mul(0x5163_3e2d));
+    let sx = permute(s % m, m, p.wrapping_mul(0xa511_e9b3));
+    let sy = permute(s / m, rows, p.wrapping_mul(0x63d8_3595));
+    let jx = randfloat(s, p.wrapping_mul(0xa399_d265));
+    let jy = randfloat(s, p.wrapping_mul(0x711a_d6a5));
+    (
+        ((s % m) as f32 + (sy as f32 + jx) / rows as f32) / m as f32,
+        ((s / m) as f32 + (sx as f32 + jy) / m as f32) / rows as f32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_TYPES: [SamplerType; 4] = [SamplerType::Independent, SamplerType::Stratified, SamplerType::Sobol, SamplerType::BlueNoise];
+
+    // I primi `count` campioni 2D della prima dimensione del pixel (x, y)
+    fn samples_2d(sampler_type: SamplerType, count: u32, seed: u32, x: u32, y: u32) -> Vec<(f32, f32)> {
+        let mut sampler = Sampler::new(sampler_type, count, seed);
+        (0..count)
+            .map(|index| {
+                sampler.start_pixel_sample(x, y, index);
+                sampler.get_2d()
+            })
+            .collect()
+    }
+
+    // Numero di strati distinti di 1/count occupati dai valori
+    fn distinct_strata(values: impl Iterator<Item = f32>, count: u32) -> usize {
+        let mut strata: Vec<u32> = values.map(|value| (value * count as f32) as u32).collect();
+        strata.sort_unstable();
+        strata.dedup();
+        strata.len()
+    }
+
+    #[test]
+    fn values_are_in_unit_interval_and_deterministic() {
+        for sampler_type in ALL_TYPES {
+            let mut sampler = Sampler::new(sampler_type, 16, 7);
+            for index in 0..16 {
+                sampler.start_pixel_sample(3, 5, index);
+                let values = [sampler.get_1d(), sampler.get_2d().0, sampler.get_2d().1, sampler.get_3d().2];
+                assert!(values.iter().all(|value| (0.0..1.0).contains(value)), "{:?}: {:?}", sampler_type, values);
+            }
+            assert_eq!(samples_2d(sampler_type, 16, 7, 3, 5), samples_2d(sampler_type, 16, 7, 3, 5));
+            assert_ne!(samples_2d(sampler_type, 16, 7, 3, 5), samples_2d(sampler_type, 16, 8, 3, 5), "{:?}", sampler_type);
+        }
+    }
+
+    #[test]
+    fn stratified_covers_every_stratum() {
+        let samples = samples_2d(SamplerType::Stratified, 16, 1, 10, 20);
+        // Un campione per cella della griglia 4 x 4 e per strato di ciascuna proiezione
+        let cells: Vec<(u32, u32)> = samples.iter().map(|&(u, v)| ((u * 4.0) as u32, (v * 4.0) as u32)).collect();
+        assert_eq!(cells.iter().collect::<std::collections::HashSet<_>>().len(), 16);
+        assert_eq!(distinct_strata(samples.iter().map(|s| s.0), 16), 16);
+        assert_eq!(distinct_strata(samples.iter().map(|s| s.1), 16), 16);
+
+        let mut sampler = Sampler::new(SamplerType::Stratified, 8, 1);
+        let values = (0..8).map(|index| {
+            sampler.start_pixel_sample(10, 20, index);
+            sampler.get_1d()
+        });
+        assert_eq!(distinct_strata(values, 8), 8);
+    }
+
+    #[test]
+    fn sobol_is_stratified_in_powers_of_two() {
+        let samples = samples_2d(SamplerType::Sobol, 32, 3, 4, 9);
+        assert_eq!(distinct_strata(samples.iter().map(|s| s.0), 32), 32);
+        assert_eq!(distinct_strata(samples.iter().map(|s| s.1), 32), 32);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::baseobject::BaseObject;
+use crate::baselight::{BaseLight, LightType};
+use crate::basematerial::BaseMaterial;
+use crate::environment::EnvironmentMap;
+use crate::meshlight::MeshLight;
+use crate::lighttree::LightTree;
+use crate::scenesettings::{LightSampling, SceneSettings};
+use crate::basecamera::BaseCamera;
+
+// Con LightSampling::Auto l'albero si usa solo oltre questo numero di luci finite:
+// con poche luci campionarle tutte costa poco e dà meno rumore
+pub const AUTO_TREE_LIGHTS: usize = 8;
+
+// Tutto ciò che serve per calcolare la radianza di un raggio: geometria, luci,
+// materiali e ambiente. Le impostazioni di qualità restano in SceneSettings.
+pub struct Scene {
+    pub obj: BaseObject,
+    pub lights: Vec<BaseLight>,
+    pub materials: Vec<BaseMaterial>,
+    pub environment: Option<EnvironmentMap>,
+    pub mesh_lights: Vec<MeshLight>,  // triangoli con materiale emissivo, campionati come luci
+    pub light_tree: Option<LightTree>, // per LightSampling::Tree e Auto, vedi build_light_tree
+}
+
+impl Scene {
+    pub fn new(obj: BaseObject, lights: Vec<BaseLight>, materials: Vec<BaseMaterial>) -> Self {
+        // Una luce a mesh per ogni materiale emissivo, con i soli triangoli che lo usano
+        let mesh_lights = materials.iter().enumerate()
+            .filter(|(_, material)| material.is_emissive())
+            .filter_map(|(index, material)| {
+                let triangles: Vec<usize> = (0..obj.vadr.len()).filter(|&triangle| obj.material_of(triangle) == index as u64).collect();
+                MeshLight::from_triangles(&obj, &triangles, material.emission)
+            })
+            .collect();
+        Scene {
+            obj,
+            lights,
+            materials,
+            environment: None,
+            mesh_lights,
+            light_tree: None,
+        }
+    }
+
+    // Va richiamata dopo aver aggiunto o spostato luci, come build_bvh per la geometria
+    pub fn build_light_tree(&mut self) {
+        self.light_tree = LightTree::build(&self.lights, &self.mesh_lights);
+    }
+
+    // Vero se un raggio campionato dalla BRDF può colpire una sorgente: luci ad area,
+    // oggetti emissivi o ambiente. Le luci puntiformi, spot e direzionali non si colpiscono.
+    pub fn has_bsdf_emitters(&self) -> bool {
+        self.environment.is_some()
+            || !self.mesh_lights.is_empty()
+            || self.lights.iter().any(|light| matches!(light.light_type, LightType::Area))
+    }
+
+    // Albero con cui campionare le luci dirette, se la modalità scelta lo prevede
+    pub fn active_light_tree(&self, light_sampling: LightSampling) -> Option<&LightTree> {
+        let use_tree = match light_sampling {
+            LightSampling::All => false,
+            LightSampling::Tree => true,
+            LightSampling::Auto => {
+                let finite_lights = self.lights.iter().filter(|light| !light.is_infinite()).count() + self.mesh_lights.len();
+                finite_lights > AUTO_TREE_LIGHTS
+            },
+        };
+        if use_tree { self.light_tree.as_ref() } else { None }
+    }
+}
+
+// Ciò che serve per renderizzare un'immagine: risoluzione, camera, scena e impostazioni
+#[derive(Clone, Copy)]
+pub struct RenderContext<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub camera: &'a BaseCamera,
+    pub scene: &'a Scene,
+    pub scene_settings: &'a SceneSettings,
+}
+
+impl<'a> RenderContext<'a> {
+    pub fn new(width: u32, height: u32, camera: &'a BaseCamera, scene: &'a Scene, scene_settings: &'a SceneSettings) -> Self {
+        RenderContext { width, height, camera, scene, scene_settings }
+    }
+}
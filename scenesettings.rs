@@ -1,3 +1,47 @@
+use crate::sampler::SamplerType;
+use crate::film::FilterType;
+
+// Integratore usato per calcolare la radianza di ogni campione
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegratorType {
+    DirectLighting,  // solo luce diretta + AO (modalità storica)
+    PathTracing,     // path tracing unidirezionale con illuminazione globale
+}
+
+// Curva con cui l'occlusione di un ostacolo diminuisce con la distanza, fino a ao_radius
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AoFalloff {
+    None,         // ogni ostacolo entro il raggio occlude completamente
+    Linear,       // 1 - d / raggio
+    Quadratic,    // (1 - d / raggio)²: contatto più marcato, transizione più morbida
+    Exponential,  // exp(-4 d / raggio), tagliata al raggio
+}
+
+// Come si distribuiscono i campioni di luce diretta fra le luci della scena
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightSampling {
+    All,   // max_samples_light campioni per ogni luce: il costo cresce con il numero di luci
+    Tree,  // max_samples_light campioni in tutto, assegnati alle luci più importanti con il LightTree
+    Auto,  // All con poche luci, Tree oltre scene::AUTO_TREE_LIGHTS luci finite
+}
+
+// Ordine in cui i worker prendono i bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BucketOrder {
+    CenterOut,  // per distanza dal centro dell'immagine: si vede subito il soggetto
+    Spiral,     // a spirale quadrata continua dal centro: ogni anello riprende dove finisce il precedente
+    Scanline,   // per righe dall'alto, da sinistra a destra
+    Hilbert,    // lungo la curva di Hilbert: bucket consecutivi sono vicini, buona coerenza di cache
+    Random,     // ordine casuale fissato da seed: dà subito un'idea dell'intera immagine
+}
+
+// Come viene suddivisa l'immagine durante il render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Progressive,  // passate sull'intera immagine fino a progressive_samples, con checkpoint e --resume
+    Buckets,      // un bucket alla volta per worker, con l'antialiasing adattivo; senza checkpoint
+}
+
 pub struct SceneSettings {
     pub quality_preset: String,
     pub aa_threshold: f32,
@@ -10,10 +54,40 @@ pub struct SceneSettings {
     pub shadow_mult: i32,
     pub dolly_in: f32,
     pub field_of_view: f32,
-    pub bucket_order: String,
+    pub bucket_order: BucketOrder,
     pub bucket_count: u32,
     pub rot_hor_camera: f32,
     pub rot_vert_camera: f32,
+    pub integrator: IntegratorType,
+    pub max_depth: u32,          // numero massimo di rimbalzi per il path tracing
+    pub rr_start_depth: u32,     // rimbalzo da cui parte la roulette russa
+    pub pixel_spread_angle: f32, // apertura angolare di un pixel (radianti), per il mip-mapping
+    pub env_intensity: f32,      // moltiplicatore della mappa d'ambiente
+    pub env_rotation: f32,       // rotazione della mappa d'ambiente attorno a Y, in gradi
+    pub env_visible: bool,       // mostra la mappa d'ambiente come sfondo dei raggi di camera
+    pub max_samples_env: u32,    // campioni di luce d'ambiente per punto
+    pub sky_enabled: bool,       // cielo di Preetham + sole al posto della mappa d'ambiente
+    pub sun_elevation: f32,      // gradi sopra l'orizzonte
+    pub sun_azimuth: f32,        // gradi, 0 = sole verso -Z
+    pub turbidity: f32,          // 2 = limpido, 10 = foschia
+    pub sky_intensity: f32,      // scala da kcd/m² del modello alle unità della scena
+    pub sun_intensity: f32,
+    pub light_sampling: LightSampling,
+    pub min_samples_aa: u32,     // campioni iniziali per pixel; si arriva a max_samples_aa solo dove l'errore supera aa_threshold
+    pub aa_heatmap: bool,        // salva i campioni usati per pixel in output_aa_samples.png
+    pub ao_radius: f32,          // distanza massima degli ostacoli per l'AO, 0 = 10% della diagonale dell'oggetto
+    pub ao_falloff: AoFalloff,
+    pub ao_pass: bool,           // salva anche l'AO da solo in output_ao.png
+    pub sampler: SamplerType,    // generatore dei campioni per AA, lente, luci, BRDF e AO
+    pub seed: u32,               // seme del render: a parità di seme l'immagine è identica bit per bit
+    pub mis_enabled: bool,       // multiple importance sampling fra luci e BRDF (euristica della potenza)
+    pub max_samples_bsdf: u32,   // raggi della BRDF per punto quando mis_enabled; senza luci ad area, mesh emissive o ambiente non se ne tracciano
+    pub pixel_filter: FilterType, // filtro di ricostruzione dei campioni AA
+    pub filter_radius: f32,      // raggio del filtro in pixel, 0 = raggio predefinito del filtro
+    pub render_mode: RenderMode,
+    pub progressive_samples: u32, // campioni per pixel a cui si ferma il render progressivo
+    pub checkpoint_interval: u32, // secondi fra due checkpoint del render progressivo, 0 = nessun checkpoint
+    pub checkpoint_file: String,  // ripreso con --resume
 }
 
 impl SceneSettings { 
@@ -30,10 +104,42 @@ impl SceneSettings {
             shadow_mult: 1,
             dolly_in: 440.0,
             field_of_view: 10.0,
-            bucket_order: "CENTRAL".to_string(),
+            bucket_order: BucketOrder::CenterOut,
             bucket_count: 75,
             rot_hor_camera: 0.0,
             rot_vert_camera: 0.0,
+            integrator: IntegratorType::DirectLighting,
+            max_depth: 5,
+            rr_start_depth: 3,
+            pixel_spread_angle: 0.0,
+            env_intensity: 1.0,
+            env_rotation: 0.0,
+            env_visible: true,
+            max_samples_env: 16,
+            sky_enabled: false,
+            sun_elevation: 45.0,
+            sun_azimuth: 135.0,
+            turbidity: 3.0,
+            sky_intensity: 0.05,
+            sun_intensity: 2.0,
+            light_sampling: LightSampling::Auto,
+            min_samples_aa: 2,
+            aa_heatmap: false,
+            ao_radius: 0.0,
+            ao_falloff: AoFalloff::Linear,
+            ao_pass: false,
+            sampler: SamplerType::Sobol,
+            seed: 0,
+            // Attivo di default: costa solo nelle scene con sorgenti che la BRDF può colpire
+            mis_enabled: true,
+            max_samples_bsdf: 8,
+            pixel_filter: FilterType::Gaussian,
+            filter_radius: 0.0,
+            // Con l'antialiasing adattivo; Progressive mostra subito tutta l'immagine e si può riprendere con --resume
+            render_mode: RenderMode::Buckets,
+            progressive_samples: 64,
+            checkpoint_interval: 300,
+            checkpoint_file: "render.checkpoint".to_string(),
         }
     }
 }
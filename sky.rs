@@ -0,0 +1,134 @@
+use crate::vector3::Vector3;
+use crate::baselight::{AGColor, BaseLight};
+use crate::environment::EnvironmentMap;
+use std::f32::consts::PI;
+
+// Diametro angolare medio del sole visto dalla Terra, in gradi
+pub const SUN_ANGULAR_DIAMETER: f32 = 0.53;
+
+// Cielo analitico di Preetham, Shirley e Smits (1999) guidato da posizione del sole e torbidità.
+// Il cielo viene convertito in una EnvironmentMap, così da riusarne lookup e importance sampling;
+// il disco solare non fa parte della mappa ma è una luce LightType::Directional.
+pub struct SkyModel {
+    pub sun_direction: Vector3,  // direzione verso il sole
+    pub turbidity: f32,          // 2 = cielo limpido, 10 = foschia
+    theta_sun: f32,
+    zenith: [f32; 3],            // Y, x, y allo zenit
+    perez: [[f32; 5]; 3],        // coefficienti A..E per Y, x, y
+}
+
+impl SkyModel {
+    // elevation e azimuth in gradi; azimuth 0 = sole verso -Z, 90 = verso +X
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        let turbidity = turbidity.clamp(1.7, 10.0);
+        let elevation = elevation.clamp(0.0, 90.0).to_radians();
+        let azimuth = azimuth.to_radians();
+        let sun_direction = Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let theta_sun = PI / 2.0 - elevation;
+        let t = turbidity;
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (th, th2, th3) = (theta_sun, theta_sun * theta_sun, theta_sun * theta_sun * theta_sun);
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_yc = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        SkyModel {
+            sun_direction,
+            turbidity,
+            theta_sun,
+            zenith: [zenith_y.max(0.0), zenith_x, zenith_yc],
+            perez,
+        }
+    }
+
+    fn perez_f(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+        (1.0 + c[0] * (c[1] / cos_theta.max(0.01)).exp())
+            * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+    }
+
+    // Radianza del cielo (in kcd/m², RGB lineare) nella direzione data
+    pub fn radiance(&self, dir: &Vector3) -> AGColor {
+        if dir.y <= 0.0 {
+            return AGColor::black();
+        }
+        let cos_theta = dir.y;
+        let gamma = dir.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let mut xyy = [0.0; 3];
+        for i in 0..3 {
+            let num = Self::perez_f(&self.perez[i], cos_theta, gamma);
+            let den = Self::perez_f(&self.perez[i], 1.0, self.theta_sun);
+            xyy[i] = self.zenith[i] * num / den;
+        }
+        xyy_to_rgb(xyy[0], xyy[1], xyy[2])
+    }
+
+    // Rende il cielo in una mappa equirettangolare; `scale` converte da kcd/m² alle unità della scena
+    pub fn to_environment_map(&self, width: usize, height: usize, scale: f32) -> EnvironmentMap {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let theta = PI * (y as f32 + 0.5) / height as f32;
+            for x in 0..width {
+                // Stessa convenzione di EnvironmentMap::uv_to_direction con rotazione nulla
+                let phi = 2.0 * PI * (x as f32 + 0.5) / width as f32;
+                let dir = Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+                pixels.push(self.radiance(&dir) * scale);
+            }
+        }
+        EnvironmentMap::from_pixels("Preetham sky".to_string(), width, height, pixels)
+    }
+
+    // Trasmittanza dell'atmosfera lungo la direzione del sole (Rayleigh + aerosol di Ångström)
+    pub fn sun_transmittance(&self) -> AGColor {
+        let theta_deg = self.theta_sun.to_degrees();
+        let air_mass = 1.0 / (self.theta_sun.cos() + 0.15 * (93.885 - theta_deg).max(0.01).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let channel = |lambda_um: f32| {
+            let rayleigh = (-0.008735 * lambda_um.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * lambda_um.powf(-1.3) * air_mass).exp();
+            rayleigh * aerosol
+        };
+        AGColor::new(channel(0.65), channel(0.57), channel(0.475))
+    }
+
+    // Il sole come luce direzionale con il diametro angolare reale, per ombre morbide corrette
+    pub fn sun_light(&self, intensity: f32) -> BaseLight {
+        BaseLight::new_directional(
+            "Sun".to_string(),
+            -self.sun_direction,
+            self.sun_transmittance(),
+            intensity,
+            SUN_ANGULAR_DIAMETER / 2.0,
+        )
+    }
+}
+
+// Da xyY (CIE) a RGB lineare sRGB / Rec. 709
+fn xyy_to_rgb(luminance: f32, x: f32, y: f32) -> AGColor {
+    if y <= 0.0 {
+        return AGColor::black();
+    }
+    let cap_x = x / y * luminance;
+    let cap_y = luminance;
+    let cap_z = (1.0 - x - y) / y * luminance;
+    AGColor::new(
+        (3.2406 * cap_x - 1.5372 * cap_y - 0.4986 * cap_z).max(0.0),
+        (-0.9689 * cap_x + 1.8758 * cap_y + 0.0415 * cap_z).max(0.0),
+        (0.0557 * cap_x - 0.2040 * cap_y + 1.0570 * cap_z).max(0.0),
+    )
+}
@@ -0,0 +1,293 @@
+use crate::vector3::Vector3;
+use crate::baselight::AGColor;
+use crate::noise::{fbm, perlin, turbulence};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    Trilinear,  // bilineare su due livelli di mip-map
+}
+
+// Dati disponibili a una texture nel punto colpito
+#[derive(Debug, Clone, Copy)]
+pub struct TextureContext {
+    pub uv: Option<(f32, f32)>,  // coordinate uv interpolate, se l'oggetto le ha
+    pub position: Vector3,       // punto colpito in coordinate di scena: mg non viene applicata ai vertici, per ora coincidono con quelle dell'oggetto
+    pub normal: Vector3,         // normale geometrica in spazio oggetto
+    pub tangent: Vector3,        // dp/du, per le normal map
+    pub bitangent: Vector3,      // dp/dv
+    pub footprint: f32,          // larghezza del cono del raggio nel punto, in unità di scena
+    pub uv_scale: f32,           // lunghezza in uv corrispondente a un'unità di scena sul triangolo
+    pub curvature: f32,          // > 0 sugli spigoli convessi, < 0 nelle cavità (vedi BaseObject::compute_curvature)
+}
+
+// Sorgente di un input del materiale (colore base, roughness, normal map).
+// Le texture procedurali e il triplanar lavorano in spazio oggetto e non richiedono uv,
+// quindi funzionano anche sui modelli STL.
+#[derive(Debug)]
+pub enum Texture {
+    Image(ImageTexture),
+    // Proiezione della bitmap lungo i tre assi, miscelata con la normale
+    Triplanar {
+        image: ImageTexture,
+        scale: f32,       // ripetizioni per unità di scena
+        sharpness: f32,   // esponente della miscela: più alto = transizioni più nette
+    },
+    Checker {
+        color1: AGColor,
+        color2: AGColor,
+        scale: f32,
+    },
+    // fBm fra due colori
+    Noise {
+        color1: AGColor,
+        color2: AGColor,
+        scale: f32,
+        octaves: u32,
+        lacunarity: f32,
+        gain: f32,
+    },
+    // Anelli concentrici attorno all'asse Y, distorti dal rumore
+    Wood {
+        color1: AGColor,
+        color2: AGColor,
+        scale: f32,
+        ring_frequency: f32,
+        distortion: f32,
+    },
+    // Venature sinusoidali lungo X perturbate dalla turbolenza
+    Marble {
+        color1: AGColor,
+        color2: AGColor,
+        scale: f32,
+        frequency: f32,
+        distortion: f32,
+    },
+    // Usura degli spigoli: color2 (metallo scoperto) sugli spigoli convessi, color1 altrove
+    EdgeWear {
+        color1: AGColor,
+        color2: AGColor,
+        threshold: f32,    // curvatura da cui inizia l'usura
+        width: f32,        // ampiezza della transizione
+        noise_scale: f32,  // frastagliatura del bordo, 0 per disattivarla
+    },
+}
+
+impl Texture {
+    // Vero se la texture legge la curvatura, che va precalcolata con BaseObject::compute_curvature
+    pub fn uses_curvature(&self) -> bool {
+        matches!(self, Texture::EdgeWear { .. })
+    }
+
+    pub fn evaluate(&self, ctx: &TextureContext) -> AGColor {
+        match self {
+            Texture::Image(image) => match ctx.uv {
+                Some((u, v)) => image.sample(u, v, image.lod(ctx.footprint * ctx.uv_scale)),
+                None => AGColor::new(1.0, 1.0, 1.0),
+            },
+            Texture::Triplanar { image, scale, sharpness } => {
+                let p = ctx.position * *scale;
+                let n = ctx.normal.normalize();
+                let mut w = Vector3::new(n.x.abs().powf(*sharpness), n.y.abs().powf(*sharpness), n.z.abs().powf(*sharpness));
+                w /= (w.x + w.y + w.z).max(1e-6);
+                let lod = image.lod(ctx.footprint * *scale);
+                image.sample(p.z, p.y, lod) * w.x + image.sample(p.x, p.z, lod) * w.y + image.sample(p.x, p.y, lod) * w.z
+            },
+            Texture::Checker { color1, color2, scale } => {
+                let p = ctx.position * *scale;
+                let parity = (p.x.floor() as i64 + p.y.floor() as i64 + p.z.floor() as i64).rem_euclid(2);
+                if parity == 0 { *color1 } else { *color2 }
+            },
+            Texture::Noise { color1, color2, scale, octaves, lacunarity, gain } => {
+                let t = fbm(&(ctx.position * *scale), *octaves, *lacunarity, *gain) * 0.5 + 0.5;
+                mix(color1, color2, t)
+            },
+            Texture::Wood { color1, color2, scale, ring_frequency, distortion } => {
+                let p = ctx.position * *scale;
+                let radius = (p.x * p.x + p.z * p.z).sqrt() + perlin(&p) * *distortion;
+                let t = (radius * *ring_frequency).fract();
+                // Anello chiaro che sfuma nel legno scuro tardivo
+                mix(color1, color2, t * t)
+            },
+            Texture::Marble { color1, color2, scale, frequency, distortion } => {
+                let p = ctx.position * *scale;
+                let t = ((p.x * *frequency + turbulence(&p, 6) * *distortion).sin()) * 0.5 + 0.5;
+                mix(color1, color2, t)
+            },
+            Texture::EdgeWear { color1, color2, threshold, width, noise_scale } => {
+                let mut curvature = ctx.curvature;
+                if *noise_scale > 0.0 {
+                    curvature += fbm(&(ctx.position * *noise_scale), 4, 2.0, 0.5) * *width;
+                }
+                let t = smoothstep(*threshold, *threshold + width.max(1e-6), curvature);
+                mix(color1, color2, t)
+            },
+        }
+    }
+}
+
+#[inline]
+fn mix(a: &AGColor, b: &AGColor, t: f32) -> AGColor {
+    let t = t.clamp(0.0, 1.0);
+    *a * (1.0 - t) + *b * t
+}
+
+#[inline]
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[derive(Debug)]
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<AGColor>,
+}
+
+// Texture bitmap (PNG/JPEG) con la catena di mip-map precalcolata
+#[derive(Debug)]
+pub struct ImageTexture {
+    pub filename: String,
+    pub wrap: WrapMode,
+    pub filter: FilterMode,
+    levels: Vec<MipLevel>,
+}
+
+impl ImageTexture {
+    // `srgb` va usato per le texture di colore; roughness e normal map sono già lineari
+    pub fn load(filename: &str, srgb: bool, wrap: WrapMode, filter: FilterMode) -> Result<ImageTexture, image::ImageError> {
+        let img = image::open(filename)?.to_rgb32f();
+        let (width, height) = img.dimensions();
+        let texels = img.pixels()
+            .map(|p| {
+                if srgb {
+                    AGColor::new(srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2]))
+                } else {
+                    AGColor::new(p[0], p[1], p[2])
+                }
+            })
+            .collect();
+
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while let Some(next) = levels.last().and_then(Self::downsample) {
+            levels.push(next);
+        }
+
+        Ok(ImageTexture {
+            filename: filename.to_string(),
+            wrap,
+            filter,
+            levels,
+        })
+    }
+
+    // Box filter 2x2 sul livello precedente; None quando si è arrivati a 1x1
+    fn downsample(level: &MipLevel) -> Option<MipLevel> {
+        if level.width == 1 && level.height == 1 {
+            return None;
+        }
+        let width = (level.width / 2).max(1);
+        let height = (level.height / 2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = AGColor::black();
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(level.width - 1);
+                    let sy = (y * 2 + dy).min(level.height - 1);
+                    sum += level.texels[(sy * level.width + sx) as usize];
+                }
+                texels.push(sum * 0.25);
+            }
+        }
+        Some(MipLevel { width, height, texels })
+    }
+
+    // Livello di mip-map per un'impronta larga `uv_footprint` in coordinate uv
+    pub fn lod(&self, uv_footprint: f32) -> f32 {
+        let base = &self.levels[0];
+        let texels = uv_footprint * base.width.max(base.height) as f32;
+        if texels <= 1.0 {
+            0.0
+        } else {
+            texels.log2().min((self.levels.len() - 1) as f32)
+        }
+    }
+
+    pub fn sample(&self, u: f32, v: f32, lod: f32) -> AGColor {
+        match self.filter {
+            FilterMode::Nearest => self.nearest(0, u, v),
+            FilterMode::Bilinear => self.bilinear(0, u, v),
+            FilterMode::Trilinear => {
+                let lower = lod.floor() as usize;
+                let upper = (lower + 1).min(self.levels.len() - 1);
+                let t = lod - lower as f32;
+                let a = self.bilinear(lower, u, v);
+                if t <= 0.0 || upper == lower {
+                    return a;
+                }
+                a * (1.0 - t) + self.bilinear(upper, u, v) * t
+            }
+        }
+    }
+
+    fn texel(&self, level: &MipLevel, x: i64, y: i64) -> AGColor {
+        let x = wrap_coord(x, level.width as i64, self.wrap);
+        let y = wrap_coord(y, level.height as i64, self.wrap);
+        level.texels[(y * level.width as i64 + x) as usize]
+    }
+
+    fn nearest(&self, level: usize, u: f32, v: f32) -> AGColor {
+        let level = &self.levels[level];
+        // v = 0 è il bordo inferiore dell'immagine
+        let x = (u * level.width as f32).floor() as i64;
+        let y = ((1.0 - v) * level.height as f32).floor() as i64;
+        self.texel(level, x, y)
+    }
+
+    fn bilinear(&self, level: usize, u: f32, v: f32) -> AGColor {
+        let level = &self.levels[level];
+        let x = u * level.width as f32 - 0.5;
+        let y = (1.0 - v) * level.height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let c00 = self.texel(level, x0, y0);
+        let c10 = self.texel(level, x0 + 1, y0);
+        let c01 = self.texel(level, x0, y0 + 1);
+        let c11 = self.texel(level, x0 + 1, y0 + 1);
+        (c00 * (1.0 - tx) + c10 * tx) * (1.0 - ty) + (c01 * (1.0 - tx) + c11 * tx) * ty
+    }
+}
+
+fn wrap_coord(c: i64, size: i64, wrap: WrapMode) -> i64 {
+    match wrap {
+        WrapMode::Repeat => c.rem_euclid(size),
+        WrapMode::Clamp => c.clamp(0, size - 1),
+        WrapMode::Mirror => {
+            let period = c.rem_euclid(2 * size);
+            if period < size { period } else { 2 * size - 1 - period }
+        }
+    }
+}
+
+#[inline]
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
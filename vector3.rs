@@ -1,7 +1,6 @@
 use glam::Vec3;  // Nota: usiamo Vec3 invece di DVec3
 use std::ops::{Add, Sub, Mul, Div, AddAssign, SubAssign, MulAssign, DivAssign, Neg, Index, IndexMut};
 use std::hash::{Hash, Hasher};
-use rand::Rng;
 
 // prova
 
@@ -50,11 +49,23 @@ impl Vector3 {
         self.to_vec3().length_squared()
     }
 
-    // #[inline(always)]
-    // pub fn reflect(&self, normal: &Vector3) -> Vector3 {
-    //     let result = self.to_vec3().reflect(normal.to_vec3());
-    //     Vector3::from_vec3(result)
-    // }
+    #[inline(always)]
+    pub fn reflect(&self, normal: &Vector3) -> Vector3 {
+        *self - *normal * 2.0 * self.dot(normal)
+    }
+
+    // Rifrazione di Snell: self è la direzione incidente, normal è rivolta contro di essa,
+    // eta è il rapporto n_incidente / n_trasmesso. None in caso di riflessione totale interna.
+    #[inline]
+    pub fn refract(&self, normal: &Vector3, eta: f32) -> Option<Vector3> {
+        let cos_i = -self.dot(normal);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+        if sin2_t > 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some((*self * eta + *normal * (eta * cos_i - cos_t)).normalize())
+    }
 
     #[inline(always)]
     pub fn min(&self, other: &Vector3) -> Vector3 {
@@ -74,35 +85,30 @@ impl Vector3 {
         }
     }
 
+    // Base ortonormale (t, b) attorno a un vettore normalizzato (Duff et al. 2017)
     #[inline]
-    pub fn random_in_hemisphere(normal: &Vector3) -> Vector3 {
-        let mut rng = rand::thread_rng();
-        let random_vector = Vector3::new(
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0),
-            rng.gen_range(-1.0..1.0)
-        ).normalize();
-        
-        if random_vector.dot(normal) > 0.0 {
-            random_vector
-        } else {
-            -random_vector
-        }
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let sign = 1.0f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        let t = Vector3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x);
+        let bt = Vector3::new(b, sign + self.y * self.y * a, -self.y);
+        (t, bt)
     }
 
+    // Porta un vettore espresso nella base locale (t, b, n) nello spazio mondo
     #[inline]
-    pub fn random_in_unit_sphere() -> Vector3 {
-        let mut rng = rand::thread_rng();
-        loop {
-            let p = Vector3::new(
-                rng.gen_range(-1.0..1.0),
-                rng.gen_range(-1.0..1.0),
-                rng.gen_range(-1.0..1.0)
-            );
-            if p.length_squared() < 1.0 {
-                return p;
-            }
-        }
+    pub fn from_local(&self, local: &Vector3) -> Vector3 {
+        let (t, b) = self.orthonormal_basis();
+        t * local.x + b * local.y + *self * local.z
+    }
+
+    // Direzione distribuita come cos(theta) attorno all'asse z, da due numeri in [0, 1)
+    #[inline]
+    pub fn cosine_sample_hemisphere(u1: f32, u2: f32) -> Vector3 {
+        let r = u1.sqrt();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        Vector3::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).max(0.0).sqrt())
     }
 
     #[inline(always)]